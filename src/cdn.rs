@@ -1,22 +1,29 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::io::SeekFrom;
+use std::io::{Cursor, SeekFrom};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use reqwest::header::RANGE;
 use reqwest::Client;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
 
 use crate::error::Error;
-use crate::tact::archive::{ArchiveIndex, ArchiveIndexEntry};
-use crate::tact::blte::decode_blte;
+use crate::ribbit::RibbitClient;
+use crate::tact::archive::{ArchiveGroupIndex, ArchiveIndex, ArchiveIndexEntry, ArchiveLookupTable, FileIndex};
+use crate::tact::blte::{decode_blte_with_keys, verify_blte, BLTEReader};
 use crate::tact::common::{CKey, EKey};
+use crate::tact::config::{BuildConfig, CDNConfig, ConfigFile, PatchConfig};
+use crate::tact::download::DownloadManifest;
 use crate::tact::encoding::EncodingFile;
+use crate::tact::install::InstallManifest;
+use crate::tact::keys::KeyStore;
 use crate::tact::manifest::Manifest;
-use crate::tact::root::RootFile;
+use crate::tact::patch::PatchManifest;
+use crate::tact::root::{RootEntryFilter, RootFile};
+use crate::tact::size::SizeManifest;
 
 #[derive(Clone)]
 pub struct CDNHost {
@@ -56,6 +63,7 @@ async fn read_or_cache<P: AsRef<Path>>(client: &Client, file_path: P, url: &str)
             let buf = client.get(url)
                 .send()
                 .await?
+                .error_for_status()?
                 .bytes()
                 .await?;
             fs::create_dir_all(file_path.as_ref().parent().unwrap())
@@ -66,7 +74,17 @@ async fn read_or_cache<P: AsRef<Path>>(client: &Client, file_path: P, url: &str)
     }
 }
 
-async fn find_matching_segment_in_dir<P: AsRef<Path>>(dir_path: P, Range { start, end }: Range<usize>) -> Result<Option<Vec<u8>>, Error> {
+// Whether the error means the file doesn't exist, as opposed to the fetch
+// failing
+fn is_not_found(e: &Error) -> bool {
+    match e {
+        Error::HTTPRequestError(e) => e.status() == Some(reqwest::StatusCode::NOT_FOUND),
+        Error::IOError(e) => e.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+async fn find_matching_segment_file_in_dir<P: AsRef<Path>>(dir_path: P, Range { start, end }: Range<usize>) -> Result<Option<(PathBuf, u64)>, Error> {
     let Ok(mut dir_list) = fs::read_dir(dir_path.as_ref()).await else {
         return Ok(None);
     };
@@ -78,16 +96,23 @@ async fn find_matching_segment_in_dir<P: AsRef<Path>>(dir_path: P, Range { start
         let file_end: usize = end_str.parse().expect("invalid filename in segments dir");
         if file_start <= start && file_end >= end {
             debug!("found matching segment {:?}", &name);
-            let mut file = fs::File::open(dir_entry.path()).await?;
-            file.seek(SeekFrom::Start((start - file_start) as u64)).await?;
-            let mut buf = vec![0; end - start];
-            file.read_exact(&mut buf).await?;
-            return Ok(Some(buf))
+            return Ok(Some((dir_entry.path(), (start - file_start) as u64)))
         }
     }
     Ok(None)
 }
 
+async fn find_matching_segment_in_dir<P: AsRef<Path>>(dir_path: P, Range { start, end }: Range<usize>) -> Result<Option<Vec<u8>>, Error> {
+    let Some((path, offset)) = find_matching_segment_file_in_dir(dir_path, start..end).await? else {
+        return Ok(None);
+    };
+    let mut file = fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = vec![0; end - start];
+    file.read_exact(&mut buf).await?;
+    Ok(Some(buf))
+}
+
 async fn read_or_cache_segment<P: AsRef<Path>>(client: &Client, file_path: P, url: &str, Range { start, end }: Range<usize>) -> Result<Vec<u8>, Error> {
     if matches!(fs::try_exists(file_path.as_ref()).await, Ok(true)) {
         debug!("cache: found {:?}", file_path.as_ref());
@@ -122,6 +147,30 @@ async fn read_or_cache_segment<P: AsRef<Path>>(client: &Client, file_path: P, ur
     }
 }
 
+// Opens a reader over the given range of a cached file or segment, only
+// falling back to reading the range into memory if it needs to be downloaded.
+async fn open_or_cache_segment<P: AsRef<Path>>(client: &Client, file_path: P, url: &str, Range { start, end }: Range<usize>) -> Result<DataReader, Error> {
+    let cached = if matches!(fs::try_exists(file_path.as_ref()).await, Ok(true)) {
+        Some((file_path.as_ref().to_path_buf(), start as u64))
+    } else {
+        let mut segment_path = file_path.as_ref().to_path_buf();
+        segment_path.set_extension("segments");
+        find_matching_segment_file_in_dir(&segment_path, start..end).await?
+    };
+
+    if let Some((path, offset)) = cached {
+        debug!("cache: streaming {:?} from offset {}", &path, offset);
+        let mut file = fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        return Ok(Box::new(file.take((end - start) as u64)));
+    }
+
+    let buf = read_or_cache_segment(client, file_path, url, start..end).await?;
+    Ok(Box::new(Cursor::new(buf)))
+}
+
+pub type DataReader = Box<dyn AsyncRead + Unpin + Send>;
+
 #[derive(Clone)]
 pub struct BlizzCache {
     pub cache_path: PathBuf,
@@ -159,6 +208,12 @@ impl BlizzCache {
         read_or_cache_segment(&self.client, filename, &host.make_url(&archive.key, "data"), range).await
     }
 
+    pub async fn open_archive_entry(&self, host: &CDNHost, archive: &ArchiveIndex, entry: &ArchiveIndexEntry) -> Result<DataReader, Error> {
+        let mut filename = self.cache_path.join("data");
+        filename.push(&archive.key);
+        open_or_cache_segment(&self.client, filename, &host.make_url(&archive.key, "data"), entry.get_byte_range()).await
+    }
+
     pub async fn fetch_archive_entries(&self, host: &CDNHost, archive: &ArchiveIndex, entries: &[&ArchiveIndexEntry]) -> Result<(usize, Vec<u8>), Error> {
         let mut filename = self.cache_path.join("data");
         filename.push(&archive.key);
@@ -174,75 +229,283 @@ impl BlizzCache {
         Ok((offset, data))
     }
     
+    // Patch blobs, patch archives and their indices live under `patch/`
+    // rather than `data/`
+    pub async fn fetch_patch(&self, host: &CDNHost, key: &str) -> Result<Vec<u8>, Error> {
+        self.fetch_data(host, "patch", key).await
+    }
+
+    pub async fn fetch_patch_manifest(&self, host: &CDNHost, manifest_key: &EKey) -> Result<PatchManifest, Error> {
+        PatchManifest::parse(&self.fetch_patch(host, &manifest_key.to_string()).await?)
+    }
+
+    pub async fn fetch_patch_archive_entry(&self, host: &CDNHost, archive: &ArchiveIndex, entry: &ArchiveIndexEntry) -> Result<Vec<u8>, Error> {
+        let mut filename = self.cache_path.join("patch");
+        filename.push(&archive.key);
+        read_or_cache_segment(&self.client, filename, &host.make_url(&archive.key, "patch"), entry.get_byte_range()).await
+    }
+
+    // CDNs don't always have the archive-group index the CDN config names, in
+    // which case this returns None so it can be built locally
+    async fn fetch_archive_group(&self, host: &CDNHost, group_key: &str) -> Result<Option<ArchiveGroupIndex>, Error> {
+        info!("fetching archive-group index {}...", group_key);
+        match self.fetch_data(host, "data", &format!("{}.index", group_key)).await {
+            Ok(group_data) => Ok(Some(ArchiveGroupIndex::parse(group_key, &group_data)?)),
+            Err(e) if is_not_found(&e) => {
+                warn!("couldn't fetch archive-group index ({}), building it locally", e);
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    // Writes locally generated data into the cache, as if it had been fetched
+    pub async fn store_data(&self, directory: &str, key: &str, data: &[u8]) -> Result<(), Error> {
+        let mut file_path = self.cache_path.join(directory);
+        file_path.push(key);
+        fs::create_dir_all(file_path.parent().unwrap()).await?;
+        fs::write(file_path, data).await?;
+        Ok(())
+    }
+
     async fn fetch_manifest(&self, manifest_name: &str) -> Result<Vec<u8>, Error> {
         let url = format!("{}/{}/{}", self.patch_server, self.product, manifest_name);
         let mut filename = self.cache_path.join("patch_server");
         filename.push(&self.product);
-        filename.push(&manifest_name);
+        filename.push(manifest_name);
         read_or_cache(&self.client, filename, &url).await
     }
 }
 
+// Where an EKey's data can be fetched from on the CDN
+#[derive(Clone, Copy)]
+pub enum DataLocation<'a> {
+    Archive(&'a ArchiveIndex, &'a ArchiveIndexEntry),
+    // A standalone `data/xx/yy/<ekey>` file, with its encoded size
+    Loose(u64),
+}
+
+impl DataLocation<'_> {
+    pub fn get_encoded_size(&self) -> u64 {
+        match self {
+            DataLocation::Archive(_, entry) => entry.size_bytes as u64,
+            DataLocation::Loose(size) => *size,
+        }
+    }
+}
+
+// Everything needed to patch files from an older build into this one
+#[derive(Clone)]
+pub struct PatchInfo {
+    pub manifest: PatchManifest,
+    pub config: PatchConfig,
+    pub archive_index: Vec<ArchiveIndex>,
+    pub file_index: Option<FileIndex>,
+}
+
+impl PatchInfo {
+    pub fn find_archive_entry(&self, patch_ekey: &EKey) -> Option<(&ArchiveIndex, &ArchiveIndexEntry)> {
+        self.archive_index.iter()
+            .find_map(|archive| Some((archive, archive.get_entry_for_ekey(patch_ekey)?)))
+    }
+}
+
+// Knobs for CDNFetcher::init_with_options which affect how the fetcher is
+// set up, rather than how it fetches.
+#[derive(Clone, Debug, Default)]
+pub struct FetcherOptions {
+    // Use EncodingFile::parse_paged, trading lookup speed for memory
+    pub paged_encoding: bool,
+    // Locale flags to prefer when a file has several variants, most preferred
+    // first. Empty keeps the RootFile default.
+    pub locales: Vec<u32>,
+    // Which root entries to consider, e.g. to skip other platforms' variants
+    pub root_filter: RootEntryFilter,
+    // Fetch the versions and cdns manifests from this Ribbit server rather
+    // than the HTTP patch server
+    pub ribbit: Option<RibbitClient>,
+    // Which build to load, by default the region's current one
+    pub build: BuildSelection,
+}
+
+// Picks the build a CDNFetcher loads. Pinning one makes it possible to
+// rebuild a sheepfile from the same data after the live version moves on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum BuildSelection {
+    // The build in the versions manifest's row for the region
+    #[default]
+    Current,
+    // The build in whichever versions manifest row has this BuildId...
+    BuildId(u64),
+    // ...or this VersionsName, e.g. "1.15.2.54261"
+    VersionsName(String),
+    // Any build, even one no longer in the versions manifest, by the hashes
+    // of its build config and CDN config
+    Configs { build_config: String, cdn_config: String },
+}
+
+impl FromStr for BuildSelection {
+    type Err = Error;
+
+    // Parses `<build config>:<cdn config>`, a BuildId or a VersionsName
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((build_config, cdn_config)) = s.split_once(':') {
+            // config hashes are MD5s, just like CKeys
+            for key in [build_config, cdn_config] {
+                CKey::from_str(key).map_err(|_| Error::InvalidBuildSelection(s.to_string()))?;
+            }
+            return Ok(BuildSelection::Configs {
+                build_config: build_config.to_ascii_lowercase(),
+                cdn_config: cdn_config.to_ascii_lowercase(),
+            });
+        }
+        match s.parse() {
+            Ok(build_id) => Ok(BuildSelection::BuildId(build_id)),
+            Err(_) => Ok(BuildSelection::VersionsName(s.to_string())),
+        }
+    }
+}
+
+impl BuildSelection {
+    // Finds the selected build's (build config, CDN config) hashes
+    pub fn get_config_keys(&self, versions: &Manifest, region: &str) -> Result<(String, String), Error> {
+        let (row, description) = match self {
+            BuildSelection::Configs { build_config, cdn_config } => return Ok((build_config.clone(), cdn_config.clone())),
+            BuildSelection::Current => (
+                versions.find_row("Region", region),
+                format!("for region {}", region),
+            ),
+            BuildSelection::BuildId(build_id) => (
+                (0..versions.rows.len()).find(|&row| versions.get_dec_field(row, "BuildId") == Some(*build_id)),
+                format!("with BuildId {}", build_id),
+            ),
+            BuildSelection::VersionsName(name) => (
+                versions.find_row("VersionsName", name),
+                format!("with VersionsName {}", name),
+            ),
+        };
+        let row = row.ok_or_else(|| Error::MissingBuild(description.clone()))?;
+        let get_key = |field| versions.get_field(row, field)
+            .filter(|key| !key.is_empty())
+            .map(|key| key.to_string())
+            .ok_or_else(|| Error::MissingBuild(description.clone()));
+        Ok((get_key("BuildConfig")?, get_key("CDNConfig")?))
+    }
+}
+
 #[derive(Clone)]
 pub struct CDNFetcher {
     pub hosts: Vec<CDNHost>,
     pub archive_index: Vec<ArchiveIndex>,
+    pub archive_lookup: ArchiveLookupTable,
     pub root: RootFile,
     pub cache: BlizzCache,
     pub encoding: EncodingFile,
     pub versions: Manifest,
     pub cdns: Manifest,
-    pub cdn_config: HashMap<String, Vec<String>>,
-    pub build_config: HashMap<String, Vec<String>>,
+    pub cdn_config: CDNConfig,
+    pub build_config: BuildConfig,
+    // the hashes the configs were fetched by, which identify the build
+    pub cdn_config_key: String,
+    pub build_config_key: String,
+    pub keys: KeyStore,
+    pub verify: bool,
 }
 
 impl CDNFetcher {
     pub async fn init<P: AsRef<Path>>(cache_path: P, patch_server: &str, product: &str, region: &str) -> Result<Self, Error> {
+        Self::init_with_options(cache_path, patch_server, product, region, &FetcherOptions::default()).await
+    }
+
+    pub async fn init_with_options<P: AsRef<Path>>(cache_path: P, patch_server: &str, product: &str, region: &str, options: &FetcherOptions) -> Result<Self, Error> {
         info!("intializing cache at {:?}", cache_path.as_ref());
         let cache = BlizzCache::new(cache_path, patch_server, product);
 
-        info!("loading versions manifest");
-        let versions = Manifest::parse(&cache.fetch_manifest("versions").await?)?;
-        info!("loading CDNs manifest");
-        let cdns = Manifest::parse(&cache.fetch_manifest("cdns").await?)?;
+        let (versions, cdns) = match &options.ribbit {
+            Some(ribbit) => {
+                info!("loading versions manifest from Ribbit server {}", &ribbit.server);
+                let versions = ribbit.fetch_versions(product).await?;
+                info!("loading CDNs manifest from Ribbit server {}", &ribbit.server);
+                (versions, ribbit.fetch_cdns(product).await?)
+            },
+            None => {
+                info!("loading versions manifest");
+                let versions = Manifest::parse(&cache.fetch_manifest("versions").await?)?;
+                info!("loading CDNs manifest");
+                (versions, Manifest::parse(&cache.fetch_manifest("cdns").await?)?)
+            },
+        };
 
-        let cdn_row = cdns.find_row("Name", region).unwrap();
-        let path = cdns.get_field(cdn_row, "Path").unwrap();
-        let hosts: Vec<CDNHost> = cdns.get_field(cdn_row, "Hosts").unwrap()
+        let cdn_row = cdns.find_row("Name", region).ok_or_else(|| Error::UnknownRegion(region.to_string()))?;
+        let (Some(path), Some(host_names)) = (cdns.get_field(cdn_row, "Path"), cdns.get_field(cdn_row, "Hosts")) else {
+            return Err(Error::UnknownRegion(region.to_string()));
+        };
+        let hosts: Vec<CDNHost> = host_names
             .split_whitespace()
             .map(|host| CDNHost::new(host, path))
             .collect();
+        if hosts.is_empty() {
+            return Err(Error::UnknownRegion(region.to_string()));
+        }
 
-        let version_row = versions.find_row("Region", region).unwrap();
-        let build_config_key = versions.get_field(version_row, "BuildConfig").unwrap();
-        let cdn_config_key = versions.get_field(version_row, "CDNConfig").unwrap();
+        let (build_config_key, cdn_config_key) = options.build.get_config_keys(&versions, region)?;
 
-        info!("fetching CDN config");
-        let cdn_config = parse_config(&String::from_utf8(cache.fetch_data(&hosts[0], "config", cdn_config_key).await?).expect("invalid config"));
-        info!("fetching build config");
-        let build_config = parse_config(&String::from_utf8(cache.fetch_data(&hosts[0], "config", build_config_key).await?).expect("invalid config"));
+        info!("fetching CDN config {}", &cdn_config_key);
+        let cdn_config = CDNConfig::parse(&cache.fetch_data(&hosts[0], "config", &cdn_config_key).await?)?;
+        info!("fetching build config {}", &build_config_key);
+        let build_config = BuildConfig::parse(&cache.fetch_data(&hosts[0], "config", &build_config_key).await?)?;
+        if let Some(build_name) = &build_config.build_name {
+            info!("loading build {}", build_name);
+        }
 
         info!("fetching encoding file");
-        let encoding_key = &build_config.get("encoding").unwrap()[1];
-        let encoding = EncodingFile::parse(&cache.fetch_data(&hosts[0], "data", encoding_key).await?)?;
+        let encoding_key = build_config.encoding_ekey.to_string();
+        let encoding_data = cache.fetch_data(&hosts[0], "data", &encoding_key).await?;
+        let encoding = if options.paged_encoding {
+            EncodingFile::parse_paged(&encoding_data)?
+        } else {
+            EncodingFile::parse(&encoding_data)?
+        };
 
-        let archive_keys = cdn_config.get("archives").unwrap();
-        let mut archive_index = Vec::new();
-        for (i, archive_key) in archive_keys.iter().enumerate() {
-            info!("[{}/{}] fetching archive index {}...", i, archive_keys.len(), archive_key);
-            let archive_data = cache.fetch_data(&hosts[0], "data", &format!("{}.index", archive_key)).await?;
-            archive_index.push(ArchiveIndex::parse(archive_key, &archive_data)?);
+        let archive_keys: Vec<String> = cdn_config.archives.iter().map(|key| key.to_string()).collect();
+        let archive_group_key = cdn_config.archive_group.as_ref().map(|key| key.to_string());
+        let archive_group = match &archive_group_key {
+            Some(group_key) => cache.fetch_archive_group(&hosts[0], group_key).await?,
+            None => None,
+        };
+
+        let archive_index = match &archive_group {
+            Some(group) => group.split(&archive_keys),
+            None => {
+                let mut archive_index = Vec::new();
+                for (i, archive_key) in archive_keys.iter().enumerate() {
+                    info!("[{}/{}] fetching archive index {}...", i, archive_keys.len(), archive_key);
+                    let archive_data = cache.fetch_data(&hosts[0], "data", &format!("{}.index", archive_key)).await?;
+                    archive_index.push(ArchiveIndex::parse(archive_key, &archive_data)?);
+                }
+                archive_index
+            },
+        };
+        if let (None, Some(group_key)) = (&archive_group, &archive_group_key) {
+            let group = ArchiveGroupIndex::build(group_key, &archive_index);
+            cache.store_data("data", &format!("{}.index", group_key), &group.to_bytes()).await?;
         }
+        info!("building archive lookup table");
+        let archive_lookup = ArchiveLookupTable::build(&archive_index);
 
         info!("fetching root file");
-        let root_ckey: CKey = CKey::from_str(&build_config.get("root").unwrap()[0]).unwrap();
-        let root_ekey = &encoding.get_ekey_for_ckey(&root_ckey).unwrap().to_string();
-        let root_data = cache.fetch_data(&hosts[0], "data", root_ekey).await?;
-        let root = RootFile::parse(&root_data)?;
+        let root_ekey = encoding.get_ekey_for_ckey(&build_config.root).ok_or(Error::MissingCKey)?;
+        let root_data = cache.fetch_data(&hosts[0], "data", &root_ekey.to_string()).await?;
+        let mut root = RootFile::parse(&root_data)?;
+        if !options.locales.is_empty() {
+            root.set_locale_preference(&options.locales);
+        }
+        root.set_entry_filter(options.root_filter.clone());
 
         Ok(CDNFetcher {
             hosts,
             archive_index,
+            archive_lookup,
             root,
             cache,
             encoding,
@@ -250,56 +513,304 @@ impl CDNFetcher {
             cdns,
             cdn_config,
             build_config,
+            cdn_config_key,
+            build_config_key,
+            keys: KeyStore::default(),
+            verify: false,
         })
     }
 
     pub fn find_archive_entry(&self, ekey: &EKey) -> Option<(&ArchiveIndex, &ArchiveIndexEntry)> {
-        for index in &self.archive_index {
-            if let Some(entry) = index.get_entry_for_ekey(ekey) {
-                return Some((index, entry));
+        self.archive_lookup.find_entry(&self.archive_index, ekey)
+    }
+
+    // Finds the first of the CKey's EKeys that's present in an archive
+    pub fn find_archive_entry_for_ckey(&self, ckey: &CKey) -> Option<(EKey, &ArchiveIndex, &ArchiveIndexEntry)> {
+        for ekey in self.encoding.get_ekeys_for_ckey(ckey)? {
+            if let Some((archive, entry)) = self.find_archive_entry(ekey) {
+                return Some((ekey.clone(), archive, entry));
             }
         }
         None
     }
 
+    // Finds where one of the CKey's EKeys can be fetched from, preferring
+    // archives and falling back to loose data files sized from the encoding file
+    pub fn find_data_location_for_ckey(&self, ckey: &CKey) -> Option<(EKey, DataLocation<'_>)> {
+        if let Some((ekey, archive, entry)) = self.find_archive_entry_for_ckey(ckey) {
+            return Some((ekey, DataLocation::Archive(archive, entry)));
+        }
+        self.encoding.get_ekeys_for_ckey(ckey)?
+            .iter()
+            .find_map(|ekey| {
+                let size = self.encoding.get_encoded_size_for_ekey(ekey)?;
+                Some((ekey.clone(), DataLocation::Loose(size)))
+            })
+    }
+
+    pub async fn fetch_loose_data(&self, ekey: &EKey, size: u64) -> Result<Vec<u8>, Error> {
+        let data = self.cache.fetch_data(&self.hosts[0], "data", &ekey.to_string()).await?;
+        if data.len() as u64 != size {
+            return Err(Error::LooseDataSizeMismatch(ekey.to_string(), size, data.len() as u64));
+        }
+        Ok(data)
+    }
+
+    pub async fn fetch_data_location(&self, ekey: &EKey, location: DataLocation<'_>) -> Result<Vec<u8>, Error> {
+        match location {
+            DataLocation::Archive(archive, entry) => self.cache.fetch_archive_entry(&self.hosts[0], archive, entry).await,
+            DataLocation::Loose(size) => self.fetch_loose_data(ekey, size).await,
+        }
+    }
+
+    pub async fn open_data_location(&self, ekey: &EKey, location: DataLocation<'_>) -> Result<DataReader, Error> {
+        match location {
+            DataLocation::Archive(archive, entry) => self.cache.open_archive_entry(&self.hosts[0], archive, entry).await,
+            DataLocation::Loose(size) => Ok(Box::new(Cursor::new(self.fetch_loose_data(ekey, size).await?))),
+        }
+    }
+
     pub async fn fetch_archive(&self, archive: &ArchiveIndex) -> Result<Vec<u8>, Error> {
         let data = self.cache.fetch_archive(&self.hosts[0], archive).await?;
         Ok(data)
     }
 
     pub async fn fetch_ckey_from_archive(&self, ckey: &CKey) -> Result<Option<Vec<u8>>, Error> {
-        let Some(ekey) = self.encoding.get_ekey_for_ckey(ckey) else {
-            return Ok(None);
-        };
-        let Some((archive, entry)) = self.find_archive_entry(ekey) else {
+        let Some((_, archive, entry)) = self.find_archive_entry_for_ckey(ckey) else {
             return Ok(None);
         };
         let data = self.cache.fetch_archive_entry(&self.hosts[0], archive, entry).await?;
         Ok(Some(data))
     }
 
+    // Fetches the build's patch manifest and config, and the CDN's patch
+    // archive and file indices. Returns None if the build has no patch data.
+    pub async fn fetch_patch_info(&self) -> Result<Option<PatchInfo>, Error> {
+        let host = &self.hosts[0];
+        let (Some(manifest_key), Some(config_key)) = (&self.build_config.patch, &self.build_config.patch_config) else {
+            return Ok(None);
+        };
+
+        info!("fetching patch manifest");
+        let manifest = self.cache.fetch_patch_manifest(host, manifest_key).await?;
+        info!("fetching patch config");
+        let config = PatchConfig::parse(&self.cache.fetch_data(host, "config", &config_key.to_string()).await?)?;
+
+        let mut archive_index = Vec::new();
+        let patch_archive_keys = &self.cdn_config.patch_archives;
+        for (i, archive_key) in patch_archive_keys.iter().enumerate() {
+            let archive_key = archive_key.to_string();
+            info!("[{}/{}] fetching patch archive index {}...", i, patch_archive_keys.len(), archive_key);
+            let archive_data = self.cache.fetch_patch(host, &format!("{}.index", archive_key)).await?;
+            archive_index.push(ArchiveIndex::parse(&archive_key, &archive_data)?);
+        }
+
+        let file_index = match &self.cdn_config.patch_file_index {
+            Some(file_index_key) => {
+                let file_index_key = file_index_key.to_string();
+                info!("fetching patch file index");
+                let file_index_data = self.cache.fetch_patch(host, &format!("{}.index", file_index_key)).await?;
+                Some(FileIndex::parse(&file_index_key, &file_index_data)?)
+            },
+            None => None,
+        };
+
+        Ok(Some(PatchInfo {
+            manifest,
+            config,
+            archive_index,
+            file_index,
+        }))
+    }
+
+    // Fetches a patch blob, from a patch archive if it's in one or otherwise
+    // as a loose file
+    pub async fn fetch_patch(&self, patch_info: &PatchInfo, patch_ekey: &EKey) -> Result<Vec<u8>, Error> {
+        match patch_info.find_archive_entry(patch_ekey) {
+            Some((archive, entry)) => self.cache.fetch_patch_archive_entry(&self.hosts[0], archive, entry).await,
+            None => self.cache.fetch_patch(&self.hosts[0], &patch_ekey.to_string()).await,
+        }
+    }
+
+    // Fetches and decodes one of the manifests the build config names (e.g.
+    // install or download). Returns None if the build config doesn't have it.
+    async fn fetch_build_manifest(&self, name: &str, file: Option<&ConfigFile>) -> Result<Option<Vec<u8>>, Error> {
+        let Some(file) = file else {
+            return Ok(None);
+        };
+        let ekey = match &file.ekey {
+            Some(ekey) => ekey.clone(),
+            None => self.encoding.get_ekey_for_ckey(&file.ckey).ok_or(Error::MissingCKey)?.clone(),
+        };
+        info!("fetching {} manifest", name);
+        let data = match self.find_archive_entry(&ekey) {
+            Some((archive, entry)) => self.cache.fetch_archive_entry(&self.hosts[0], archive, entry).await?,
+            None => self.cache.fetch_data(&self.hosts[0], "data", &ekey.to_string()).await?,
+        };
+        Ok(Some(self.decode_blte(&ekey, &data)?))
+    }
+
+    pub async fn fetch_install_manifest(&self) -> Result<Option<InstallManifest>, Error> {
+        self.fetch_build_manifest("install", self.build_config.install.as_ref()).await?
+            .map(|data| InstallManifest::parse(&data))
+            .transpose()
+    }
+
+    pub async fn fetch_download_manifest(&self) -> Result<Option<DownloadManifest>, Error> {
+        self.fetch_build_manifest("download", self.build_config.download.as_ref()).await?
+            .map(|data| DownloadManifest::parse(&data))
+            .transpose()
+    }
+
+    // Older builds have no size manifest
+    pub async fn fetch_size_manifest(&self) -> Result<Option<SizeManifest>, Error> {
+        self.fetch_build_manifest("size", self.build_config.size.as_ref()).await?
+            .map(|data| SizeManifest::parse(&data))
+            .transpose()
+    }
+
+    // The CKeys of the download manifest's files at or above the given
+    // priority, for use as a RootEntryFilter's ckeys
+    pub fn get_ckeys_with_download_priority(&self, download: &DownloadManifest, max_priority: i8) -> HashSet<CKey> {
+        download.get_ekeys_with_priority(max_priority).iter()
+            .filter_map(|ekey| self.encoding.get_ckey_for_ekey(ekey).cloned())
+            .collect()
+    }
+
+    // Decodes BLTE data for the given EKey, checking its checksums first if
+    // verification is enabled.
+    pub fn decode_blte(&self, ekey: &EKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if self.verify {
+            verify_blte(data, Some(ekey))?;
+        }
+        decode_blte_with_keys(data, &self.keys)
+    }
+
+    // Starts streaming decode of BLTE data for the given EKey, checking its
+    // header and chunk checksums as it goes if verification is enabled. The
+    // chunk table is always checked against the data's encoded size.
+    pub async fn open_blte<R: AsyncRead + Unpin>(&self, ekey: &EKey, encoded_size: u64, reader: R) -> Result<BLTEReader<'_, R>, Error> {
+        let mut blte = BLTEReader::from_async_reader(reader, &self.keys).await?;
+        blte.check_encoded_size(encoded_size as usize)?;
+        if self.verify {
+            blte.verify_ekey(ekey)?;
+            blte.verify = true;
+        }
+        Ok(blte)
+    }
+
+    async fn fetch_and_decode_ckey(&self, ckey: &CKey) -> Result<Vec<u8>, Error> {
+        let (ekey, location) = self.find_data_location_for_ckey(ckey).ok_or(Error::MissingCKey)?;
+        let compressed_data = self.fetch_data_location(&ekey, location).await?;
+        self.decode_blte(&ekey, &compressed_data)
+    }
+
     pub async fn fetch_file_id(&self, file_id: u32) -> Result<Vec<u8>, Error> {
         let ckey = self.root.get_ckey_for_file_id(file_id).ok_or(Error::MissingFileId(file_id))?;
-        let compressed_data = self.fetch_ckey_from_archive(ckey).await?.ok_or(Error::MissingCKey)?;
-        decode_blte(&compressed_data)
+        self.fetch_and_decode_ckey(ckey).await
     }
 
     pub async fn fetch_file_name(&self, path: &str) -> Result<Vec<u8>, Error> {
         let ckey = self.root.get_ckey_for_file_path(path).ok_or(Error::MissingFileName(path.to_string()))?;
-        let compressed_data = self.fetch_ckey_from_archive(ckey).await?.ok_or(Error::MissingCKey)?;
-        decode_blte(&compressed_data)
+        self.fetch_and_decode_ckey(ckey).await
     }
 }
 
-fn parse_config(data: &str) -> HashMap<String, Vec<String>> {
-    let mut result = HashMap::new();
-    for line in data.lines() {
-        if line.is_empty() || line.starts_with('#') {
-            continue
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILD_CONFIG: &str = "f4b2ab2a4a4f6c7a8d5d0fc3b0e1a9b2";
+    const CDN_CONFIG: &str = "9e1a4e4d4b2c9a1f6e0e2f1b1f4a0c6d";
+
+    // Answers a single HTTP request with the given status and body, returning
+    // a host to request from and the path that was requested
+    async fn serve_http_once(status: &'static str, body: Vec<u8>) -> (CDNHost, tokio::sync::oneshot::Receiver<String>) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = CDNHost::new(&listener.local_addr().unwrap().to_string(), "tpr/wow");
+        let (path_tx, path_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.split();
+            let mut request_line = String::new();
+            tokio::io::BufReader::new(reader).read_line(&mut request_line).await.unwrap();
+            let path = request_line.split(' ').nth(1).unwrap_or_default().to_string();
+            path_tx.send(path).unwrap();
+            let headers = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            writer.write_all(headers.as_bytes()).await.unwrap();
+            writer.write_all(&body).await.unwrap();
+        });
+        (host, path_rx)
+    }
+
+    fn make_test_cache(name: &str) -> BlizzCache {
+        let cache_path = std::env::temp_dir().join(format!("polymorph-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&cache_path);
+        BlizzCache::new(cache_path, "http://127.0.0.1:1", "wow")
+    }
+
+    #[tokio::test]
+    async fn test_fetch_archive_group() {
+        let cache = make_test_cache("archive-group");
+
+        // only a missing index is built locally
+        let (host, path) = serve_http_once("404 Not Found", Vec::new()).await;
+        assert!(cache.fetch_archive_group(&host, CDN_CONFIG).await.unwrap().is_none());
+        assert_eq!(path.await.unwrap(), format!("/tpr/wow/data/9e/1a/{}.index", CDN_CONFIG));
+
+        let (host, _) = serve_http_once("500 Internal Server Error", Vec::new()).await;
+        assert!(matches!(cache.fetch_archive_group(&host, CDN_CONFIG).await, Err(Error::HTTPRequestError(_))));
+
+        let _ = std::fs::remove_dir_all(&cache.cache_path);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_patch_manifest() {
+        let cache = make_test_cache("patch-manifest");
+        let manifest_key: EKey = BUILD_CONFIG.parse().unwrap();
+
+        // a manifest without any blocks
+        let mut manifest = b"PA".to_vec();
+        manifest.extend([2, 16, 16, 16, 10]);
+        manifest.extend(0u16.to_be_bytes());
+        manifest.push(0);
+        manifest.extend([0xaa; 32]);
+        manifest.extend(1000u32.to_be_bytes());
+        manifest.extend(500u32.to_be_bytes());
+        manifest.extend([1, b'z']);
+
+        let (host, path) = serve_http_once("200 OK", manifest.clone()).await;
+        let parsed = cache.fetch_patch_manifest(&host, &manifest_key).await.unwrap();
+        assert_eq!(parsed.encoding_ckey, CKey([0xaa; 16]));
+        assert_eq!(path.await.unwrap(), format!("/tpr/wow/patch/f4/b2/{}", BUILD_CONFIG));
+        assert_eq!(std::fs::read(cache.cache_path.join("patch").join(BUILD_CONFIG)).unwrap(), manifest);
+
+        let _ = std::fs::remove_dir_all(&cache.cache_path);
+    }
 
-        let (k, v) = line.split_once(" = ").expect("invalid line");
-        result.insert(k.to_string(), v.split(' ').map(|s| s.to_string()).collect());
+    #[test]
+    fn test_build_selection() {
+        let versions = Manifest::parse(format!("Region!STRING:0|BuildConfig!HEX:16|CDNConfig!HEX:16|BuildId!DEC:4|VersionsName!String:0
+us|{BUILD_CONFIG}|{CDN_CONFIG}|54261|1.15.2.54261
+eu|{CDN_CONFIG}|{BUILD_CONFIG}|54262|1.15.2.54262
+").as_bytes()).unwrap();
+        let configs = (BUILD_CONFIG.to_string(), CDN_CONFIG.to_string());
+        let swapped = (CDN_CONFIG.to_string(), BUILD_CONFIG.to_string());
+
+        assert_eq!(BuildSelection::Current.get_config_keys(&versions, "us").unwrap(), configs);
+        assert_eq!(BuildSelection::Current.get_config_keys(&versions, "eu").unwrap(), swapped);
+        assert!(matches!(BuildSelection::Current.get_config_keys(&versions, "kr"), Err(Error::MissingBuild(_))));
+
+        let by_id: BuildSelection = "54262".parse().unwrap();
+        assert_eq!(by_id, BuildSelection::BuildId(54262));
+        assert_eq!(by_id.get_config_keys(&versions, "us").unwrap(), swapped);
+        let by_name: BuildSelection = "1.15.2.54261".parse().unwrap();
+        assert_eq!(by_name.get_config_keys(&versions, "eu").unwrap(), configs);
+        assert!("1.15.2.1".parse::<BuildSelection>().unwrap().get_config_keys(&versions, "us").is_err());
+
+        let pinned: BuildSelection = format!("{}:{}", CDN_CONFIG, BUILD_CONFIG.to_uppercase()).parse().unwrap();
+        assert_eq!(pinned.get_config_keys(&versions, "kr").unwrap(), swapped);
+        assert!(matches!("abc:def".parse::<BuildSelection>(), Err(Error::InvalidBuildSelection(_))));
     }
-    result
 }
@@ -15,10 +15,94 @@ pub enum Error {
     #[cfg(feature = "tact")]
     #[error("Invalid Zlib")]
     ZlibError(miniz_oxide::inflate::DecompressError),
+    #[error("BLTE chunk {0} has an invalid LZ4 frame")]
+    InvalidLz4Frame(usize),
+    #[error("BLTE chunk {0} has too many nested frames")]
+    BlteFramesTooDeep(usize),
+    #[error("Unknown BLTE frame type {0}")]
+    UnknownBlteFrame(char),
+    #[error("BLTE chunk {0} extends past the end of the data")]
+    TruncatedBlteChunk(usize),
+    #[error("BLTE chunk {0} doesn't match its checksum")]
+    BlteChunkChecksumMismatch(usize),
+    #[error("BLTE data offset {0} doesn't match its chunk table")]
+    InvalidBlteDataOffset(usize),
+    #[error("BLTE header doesn't match EKey {0}")]
+    BlteEKeyMismatch(String),
+    #[error("Invalid ESpec {0}")]
+    InvalidESpec(String),
+    #[error("Encoding file is truncated")]
+    TruncatedEncodingFile,
+    #[error("Encoding file {0} page {1} doesn't match its checksum")]
+    EncodingPageChecksumMismatch(&'static str, usize),
+    #[error("Encoding file {0} page {1} doesn't match the page index")]
+    InvalidEncodingPageIndex(&'static str, usize),
+    #[error("Loose data file {0} should be {1} bytes, but is {2}")]
+    LooseDataSizeMismatch(String, u64, u64),
+    #[error("Archive index is truncated")]
+    TruncatedArchiveIndex,
+    #[error("Unsupported archive index offset size {0}")]
+    UnsupportedArchiveIndexOffsetSize(u8),
+    #[error("Unsupported archive index {0} {1}")]
+    UnsupportedArchiveIndexFooter(&'static str, u8),
+    #[error("Archive index footer doesn't match its checksum")]
+    ArchiveIndexFooterChecksumMismatch,
+    #[error("Archive index table of contents doesn't match its checksum")]
+    ArchiveIndexTocChecksumMismatch,
+    #[error("Archive index block {0} doesn't match its checksum")]
+    ArchiveIndexBlockChecksumMismatch(usize),
+    #[error("Archive index block {0} doesn't match the table of contents")]
+    ArchiveIndexTocMismatch(usize),
+    #[error("Archive index should have {0} entries, but has {1}")]
+    ArchiveIndexEntryCountMismatch(u32, usize),
+    #[error("Patch manifest is truncated")]
+    TruncatedPatchManifest,
+    #[error("Patch manifest block {0} doesn't match its checksum")]
+    PatchBlockChecksumMismatch(usize),
+    #[error("Unknown manifest tag {0}")]
+    UnknownTag(String),
+    #[error("Unsupported root file version {0}")]
+    UnsupportedRootVersion(u32),
+    #[error("Root file block {0} is invalid or truncated")]
+    InvalidRootBlock(usize),
+    #[error("Unknown locale {0}")]
+    UnknownLocale(String),
+    #[error("Unknown content flag {0}")]
+    UnknownContentFlag(String),
+    #[error("Versions manifest has no build {0}")]
+    MissingBuild(String),
+    #[error("Invalid build {0}, expected <build config>:<cdn config>, a BuildId or a VersionsName")]
+    InvalidBuildSelection(String),
+    #[error("CDNs manifest has no hosts for region {0}")]
+    UnknownRegion(String),
+    #[error("Invalid Ribbit response: {0}")]
+    InvalidRibbitResponse(&'static str),
+    #[error("Ribbit response doesn't match its checksum")]
+    RibbitChecksumMismatch,
+    #[error("Timed out talking to Ribbit server {0}")]
+    RibbitTimeout(String),
+    #[error("Invalid manifest header field {0}")]
+    InvalidManifestHeader(String),
+    #[error("Invalid manifest line {0}")]
+    InvalidManifestLine(usize),
+    #[error("Invalid manifest row {0}")]
+    InvalidManifestRow(String),
+    #[error("Invalid config line {0}")]
+    InvalidConfigLine(usize),
+    #[error("Config is missing {0}")]
+    MissingConfigKey(String),
+    #[error("Invalid config value for {0}")]
+    InvalidConfigValue(String),
+    #[error("Invalid listfile line {0}")]
+    InvalidListfileLine(usize),
     #[error("Couldn't find file id {0}")]
     MissingFileId(u32),
     #[error("Couldn't find file with path {0}")]
     MissingFileName(String),
-    #[error("BLTE for file contains an encrypted frame, which we don't support")]
-    UnsupportedEncryptedData,
+    #[error("BLTE frame is encrypted with unknown TACT key {0:016X}")]
+    MissingTactKey(u64),
+    #[error("Unsupported BLTE encryption type {0}")]
+    UnsupportedEncryptionType(char),
+    #[error("Invalid TACT key entry: {0}")]
+    InvalidTactKey(String),
 }
@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use polymorph::error::Error;
+use polymorph::hashpath::hash_normalized_path;
 use polymorph::sheepfile::reader::SheepfileReader;
 
 fn make_name_variants(name: &str) -> Vec<String> {
@@ -36,10 +37,7 @@ async fn main() -> Result<(), Error> {
     let target = entry.name_hash;
     println!("looking for hash value {} / {:X}...", target, target);
     for variant in make_name_variants(name) {
-        let mut hash = hashers::jenkins::lookup3(&variant.as_bytes());
-        let high = hash & 0xffffffff00000000;
-        let low = hash & 0x00000000ffffffff;
-        hash = high >> 32 | low << 32;
+        let hash = hash_normalized_path(&variant);
         if hash == target {
             println!("\n\n!!!! SUCCESS: \"{}\" !!!!", variant);
             return Ok(());
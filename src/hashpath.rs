@@ -0,0 +1,94 @@
+// WoW's file name hash, used by root files and sheepfiles to look up entries
+// by path. It's Bob Jenkins' hashlittle2 (from lookup3.c) over the normalized
+// path, with the primary hash in the high 32 bits. See
+// https://wowdev.wiki/TACT#hashpath
+
+fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *a = a.wrapping_sub(*c); *a ^= c.rotate_left(4); *c = c.wrapping_add(*b);
+    *b = b.wrapping_sub(*a); *b ^= a.rotate_left(6); *a = a.wrapping_add(*c);
+    *c = c.wrapping_sub(*b); *c ^= b.rotate_left(8); *b = b.wrapping_add(*a);
+    *a = a.wrapping_sub(*c); *a ^= c.rotate_left(16); *c = c.wrapping_add(*b);
+    *b = b.wrapping_sub(*a); *b ^= a.rotate_left(19); *a = a.wrapping_add(*c);
+    *c = c.wrapping_sub(*b); *c ^= b.rotate_left(4); *b = b.wrapping_add(*a);
+}
+
+fn finalize(a: &mut u32, b: &mut u32, c: &mut u32) {
+    *c ^= *b; *c = c.wrapping_sub(b.rotate_left(14));
+    *a ^= *c; *a = a.wrapping_sub(c.rotate_left(11));
+    *b ^= *a; *b = b.wrapping_sub(a.rotate_left(25));
+    *c ^= *b; *c = c.wrapping_sub(b.rotate_left(16));
+    *a ^= *c; *a = a.wrapping_sub(c.rotate_left(4));
+    *b ^= *a; *b = b.wrapping_sub(a.rotate_left(14));
+    *c ^= *b; *c = c.wrapping_sub(b.rotate_left(24));
+}
+
+fn read_le_word(bytes: &[u8]) -> u32 {
+    let mut word = [0; 4];
+    word[..bytes.len()].copy_from_slice(bytes);
+    u32::from_le_bytes(word)
+}
+
+// Returns the (primary, secondary) hashes of the data, given initial values
+// for each
+pub fn hashlittle2(data: &[u8], primary: u32, secondary: u32) -> (u32, u32) {
+    let init = 0xdeadbeef_u32.wrapping_add(data.len() as u32).wrapping_add(primary);
+    let (mut a, mut b, mut c) = (init, init, init.wrapping_add(secondary));
+
+    let mut rest = data;
+    while rest.len() > 12 {
+        a = a.wrapping_add(read_le_word(&rest[0..4]));
+        b = b.wrapping_add(read_le_word(&rest[4..8]));
+        c = c.wrapping_add(read_le_word(&rest[8..12]));
+        mix(&mut a, &mut b, &mut c);
+        rest = &rest[12..];
+    }
+    if rest.is_empty() {
+        return (c, b);
+    }
+
+    // the last (up to 12) bytes are added as if zero-padded
+    let mut last = [0; 12];
+    last[..rest.len()].copy_from_slice(rest);
+    a = a.wrapping_add(read_le_word(&last[0..4]));
+    b = b.wrapping_add(read_le_word(&last[4..8]));
+    c = c.wrapping_add(read_le_word(&last[8..12]));
+    finalize(&mut a, &mut b, &mut c);
+    (c, b)
+}
+
+// Paths are hashed uppercased and with backslash separators
+pub fn normalize_path(path: &str) -> String {
+    path.to_ascii_uppercase().replace('/', "\\")
+}
+
+pub fn hash_normalized_path(normalized: &str) -> u64 {
+    let (primary, secondary) = hashlittle2(normalized.as_bytes(), 0, 0);
+    ((primary as u64) << 32) | secondary as u64
+}
+
+pub fn hash_path(path: &str) -> u64 {
+    hash_normalized_path(&normalize_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashlittle2() {
+        // from the driver5 self-test in lookup3.c
+        assert_eq!(hashlittle2(b"", 0, 0), (0xdeadbeef, 0xdeadbeef));
+        assert_eq!(hashlittle2(b"", 0, 0xdeadbeef), (0xbd5b7dde, 0xdeadbeef));
+        assert_eq!(hashlittle2(b"", 0xdeadbeef, 0xdeadbeef), (0x9c093ccd, 0xbd5b7dde));
+        assert_eq!(hashlittle2(b"Four score and seven years ago", 0, 0), (0x17770551, 0xce7226e6));
+        assert_eq!(hashlittle2(b"Four score and seven years ago", 0, 1), (0xe3607cae, 0xbd371de4));
+        assert_eq!(hashlittle2(b"Four score and seven years ago", 1, 0), (0xcd628161, 0x6cbea4b3));
+    }
+
+    #[test]
+    fn test_hash_path() {
+        assert_eq!(hash_path("a/b.blp"), hash_path("A\\B.BLP"));
+        assert_eq!(hash_normalized_path("Four score and seven years ago"), 0x17770551ce7226e6);
+        assert_eq!(hash_normalized_path(""), 0xdeadbeefdeadbeef);
+    }
+}
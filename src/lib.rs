@@ -1,6 +1,10 @@
 pub mod error;
+pub mod hashpath;
+pub mod listfile;
 #[cfg(feature = "tact")]
 pub mod tact;
 #[cfg(feature = "cdn")]
 pub mod cdn;
+#[cfg(feature = "cdn")]
+pub mod ribbit;
 pub mod sheepfile;
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::hashpath::{hash_path, normalize_path};
+
+// A community listfile, mapping file IDs to their real paths. Each line is
+// `fileid;path`, e.g. from https://github.com/wowdev/wow-listfile
+#[derive(Clone, Debug, Default)]
+pub struct Listfile {
+    // (file id, path) pairs, sorted by file id
+    pub entries: Vec<(u32, String)>,
+    file_id_to_entry_index: HashMap<u32, usize>,
+    // keyed by normalized path, so lookups ignore case and slash direction
+    path_to_entry_index: HashMap<String, usize>,
+}
+
+// A listfile path which doesn't hash to the name hash stored for its file ID
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameHashMismatch {
+    pub file_id: u32,
+    pub path: String,
+    pub stored_hash: u64,
+    pub path_hash: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NameHashReport {
+    // file IDs whose listfile path hashes to their stored name hash
+    pub matching: Vec<u32>,
+    pub mismatching: Vec<NameHashMismatch>,
+    // file IDs which are in the listfile but have no stored name hash
+    pub unhashed: Vec<u32>,
+}
+
+impl Listfile {
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut entries = Vec::new();
+        for (line_number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (file_id, path) = line.split_once(';')
+                .ok_or(Error::InvalidListfileLine(line_number + 1))?;
+            let file_id: u32 = file_id.trim().parse()
+                .map_err(|_| Error::InvalidListfileLine(line_number + 1))?;
+            entries.push((file_id, path.to_string()));
+        }
+        entries.sort_by_key(|(file_id, _)| *file_id);
+        entries.dedup_by_key(|(file_id, _)| *file_id);
+
+        let mut file_id_to_entry_index = HashMap::new();
+        let mut path_to_entry_index = HashMap::new();
+        for (i, (file_id, path)) in entries.iter().enumerate() {
+            file_id_to_entry_index.insert(*file_id, i);
+            path_to_entry_index.insert(normalize_path(path), i);
+        }
+        Ok(Listfile {
+            entries,
+            file_id_to_entry_index,
+            path_to_entry_index,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get_path_for_file_id(&self, file_id: u32) -> Option<&str> {
+        let index = *self.file_id_to_entry_index.get(&file_id)?;
+        Some(&self.entries[index].1)
+    }
+
+    pub fn get_file_id_for_path(&self, path: &str) -> Option<u32> {
+        let index = *self.path_to_entry_index.get(&normalize_path(path))?;
+        Some(self.entries[index].0)
+    }
+
+    // Finds every (file id, path) whose path matches a glob pattern, where `*`
+    // matches any run of characters (including separators) and `?` matches a
+    // single character. Matching ignores case and slash direction.
+    pub fn glob<'a>(&'a self, pattern: &str) -> impl Iterator<Item=(u32, &'a str)> + 'a {
+        let pattern = normalize_path(pattern).into_bytes();
+        self.entries.iter()
+            .filter(move |(_, path)| glob_matches(&pattern, normalize_path(path).as_bytes()))
+            .map(|(file_id, path)| (*file_id, path.as_str()))
+    }
+
+    // Checks each file's listfile path against its stored name hash
+    pub fn check_name_hashes<I: IntoIterator<Item=(u32, Option<u64>)>>(&self, stored_hashes: I) -> NameHashReport {
+        let mut report = NameHashReport::default();
+        for (file_id, stored_hash) in stored_hashes {
+            let Some(path) = self.get_path_for_file_id(file_id) else {
+                continue;
+            };
+            let Some(stored_hash) = stored_hash else {
+                report.unhashed.push(file_id);
+                continue;
+            };
+            let path_hash = hash_path(path);
+            if path_hash == stored_hash {
+                report.matching.push(file_id);
+            } else {
+                report.mismatching.push(NameHashMismatch {
+                    file_id,
+                    path: path.to_string(),
+                    stored_hash,
+                    path_hash,
+                });
+            }
+        }
+        report.matching.sort();
+        report.mismatching.sort_by_key(|mismatch| mismatch.file_id);
+        report.unhashed.sort();
+        report
+    }
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    // iterative wildcard matching, backtracking to the most recent `*`
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listfile() {
+        let listfile = Listfile::parse("53183;world/maps/azeroth/azeroth.wdt\r\n\n190086;World/Azeroth/Redridge/a.blp\n12;Interface/Icons/b.blp\n").unwrap();
+        assert_eq!(listfile.len(), 3);
+        assert_eq!(listfile.entries[0].0, 12);
+        assert_eq!(listfile.get_path_for_file_id(190086), Some("World/Azeroth/Redridge/a.blp"));
+        assert_eq!(listfile.get_file_id_for_path("WORLD\\AZEROTH\\REDRIDGE\\A.BLP"), Some(190086));
+        assert_eq!(listfile.get_file_id_for_path("nope.blp"), None);
+
+        let matches: Vec<u32> = listfile.glob("world/*.blp").map(|(file_id, _)| file_id).collect();
+        assert_eq!(matches, vec![190086]);
+        let matches: Vec<u32> = listfile.glob("*/azeroth/???????.wdt").map(|(file_id, _)| file_id).collect();
+        assert_eq!(matches, vec![53183]);
+        assert_eq!(listfile.glob("*").count(), 3);
+
+        assert!(Listfile::parse("abc;def").is_err());
+        assert!(Listfile::parse("12").is_err());
+    }
+
+    #[test]
+    fn test_listfile_check_name_hashes() {
+        let listfile = Listfile::parse("1;a.blp\n2;b.blp\n3;c.blp\n").unwrap();
+        let report = listfile.check_name_hashes([
+            (1, Some(hash_path("A.BLP"))),
+            (2, Some(hash_path("wrong.blp"))),
+            (3, None),
+            (4, Some(hash_path("d.blp"))),
+        ]);
+        assert_eq!(report.matching, vec![1]);
+        assert_eq!(report.mismatching, vec![NameHashMismatch {
+            file_id: 2,
+            path: "b.blp".to_string(),
+            stored_hash: hash_path("wrong.blp"),
+            path_hash: hash_path("b.blp"),
+        }]);
+        assert_eq!(report.unhashed, vec![3]);
+    }
+}
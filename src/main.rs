@@ -1,8 +1,8 @@
 use std::{io::SeekFrom, path::PathBuf};
 
-use clap::{arg, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use log::info;
-use polymorph::{cdn::CDNFetcher, error::Error, sheepfile::{get_data_filename, reader::SheepfileReader, writer::SheepfileWriter, Entry, INDEX_FILENAME}};
+use polymorph::{cdn::{BuildSelection, CDNFetcher, FetcherOptions}, error::Error, listfile::Listfile, ribbit::{RibbitClient, RibbitVersion, RIBBIT_SERVER}, tact::{keys::KeyStore, root::{parse_content_flag, parse_locale, RootEntryFilter}}, sheepfile::{get_data_filename, reader::SheepfileReader, writer::SheepfileWriter, Entry, INDEX_FILENAME}};
 use tokio::{fs, io::{AsyncReadExt, AsyncSeekExt}};
 
 const PATCH_SERVER: &str = "http://us.patch.battle.net:1119";
@@ -35,10 +35,78 @@ enum Commands {
 
         #[arg(short, long, value_name = "FILE")]
         out_path: PathBuf,
+
+        #[arg(short, long, value_name = "FILE")]
+        listfile_path: Option<PathBuf>,
+    },
+    List {
+        #[arg(default_value = "*")]
+        pattern: String,
+
+        #[arg(short, long, value_name = "FILE")]
+        listfile_path: PathBuf,
+
+        /// Report which listfile paths do and don't hash to the stored name hashes
+        #[arg(long)]
+        check_hashes: bool,
     },
     Create {
         #[arg(short, long, value_name = "FILE")]
         cache_path: PathBuf,
+
+        /// TACT key list for decrypting encrypted files, one `<key name> <key>`
+        /// hex pair per line. Files using unknown keys are skipped.
+        #[arg(short, long, value_name = "FILE")]
+        keys_path: Option<PathBuf>,
+
+        /// Check each file's EKey and BLTE chunk checksums, skipping files
+        /// that don't match
+        #[arg(long)]
+        verify: bool,
+
+        /// Keep the encoding file as its raw pages instead of in hash maps,
+        /// trading lookup speed for much less memory
+        #[arg(long)]
+        low_memory: bool,
+
+        /// Preferred locales for files with per-locale variants, e.g. `-l enUS -l enGB`
+        #[arg(short, long, value_name = "LOCALE")]
+        locale: Vec<String>,
+
+        /// Only include files with this content flag, e.g. `--require-flag windows`
+        #[arg(long, value_name = "FLAG")]
+        require_flag: Vec<String>,
+
+        /// Leave out files with this content flag, e.g. `--exclude-flag low-violence`
+        #[arg(long, value_name = "FLAG")]
+        exclude_flag: Vec<String>,
+
+        /// Only include files in the download manifest at or above this
+        /// priority, e.g. 0 for what's needed before the game can start
+        #[arg(long, value_name = "PRIORITY")]
+        download_priority: Option<i8>,
+
+        /// Fetch the versions and cdns manifests over Ribbit instead of HTTP,
+        /// optionally from a specific host:port
+        #[arg(long, value_name = "SERVER", num_args = 0..=1, default_missing_value = RIBBIT_SERVER)]
+        ribbit: Option<String>,
+
+        /// Pin the wow_classic build instead of using the current one, as
+        /// `<build config>:<cdn config>`, a BuildId or a VersionsName
+        #[arg(long, value_name = "BUILD")]
+        classic_build: Option<BuildSelection>,
+
+        /// Same as --classic-build, for wow_classic_era
+        #[arg(long, value_name = "BUILD")]
+        era_build: Option<BuildSelection>,
+    },
+    /// Reports the install and download sizes of each of a product's tags
+    Footprint {
+        #[arg(short, long, value_name = "FILE")]
+        cache_path: PathBuf,
+
+        #[arg(default_value = "wow_classic")]
+        product: String,
     },
 }
 
@@ -48,7 +116,28 @@ async fn get_entry_data<P: AsRef<std::path::Path>>(path: P, entry: &Entry) -> Re
     file.seek(SeekFrom::Start(entry.start_bytes as u64)).await?;
     let mut buf = vec![0; entry.size_bytes as usize];
     file.read_exact(&mut buf).await?;
-    return Ok(buf)
+    Ok(buf)
+}
+
+// Narrows the fetcher's root filter down to the files in its download
+// manifest at or above the given priority
+async fn restrict_to_download_priority(fetcher: &mut CDNFetcher, max_priority: i8) -> Result<(), Error> {
+    let Some(download) = fetcher.fetch_download_manifest().await? else {
+        info!("build has no download manifest, not restricting by priority");
+        return Ok(());
+    };
+    let ckeys = fetcher.get_ckeys_with_download_priority(&download, max_priority);
+    info!("restricting to {} files with download priority <= {}", ckeys.len(), max_priority);
+    let filter = RootEntryFilter {
+        ckeys: Some(ckeys),
+        ..fetcher.root.get_entry_filter().clone()
+    };
+    fetcher.root.set_entry_filter(filter);
+    Ok(())
+}
+
+fn parse_content_flags(names: &[String]) -> Result<u32, Error> {
+    names.iter().try_fold(0, |flags, name| Ok(flags | parse_content_flag(name)?))
 }
 
 async fn new_sheepfile<P: AsRef<std::path::Path>>(path: P) -> Result<SheepfileReader, Error> {
@@ -71,24 +160,93 @@ async fn main() -> Result<(), Error> {
             dbg!(&entry);
             println!("Found {} (name hash {}), wrote {} bytes to {:?}", entry.file_id, entry.name_hash, data.len(), &out_path);
         },
-        Commands::GetName { name, out_path } => {
+        Commands::GetName { name, out_path, listfile_path } => {
             let sheepfile = new_sheepfile(&cli.sheepfile_path).await?;
-            let entry = sheepfile.get_entry_for_name(&name)
-                .ok_or(Error::MissingFileName(name))?;
+            let entry = match listfile_path {
+                Some(listfile_path) => {
+                    let listfile = Listfile::parse(&fs::read_to_string(listfile_path).await?)?;
+                    sheepfile.get_entry_for_listfile_path(&listfile, &name)
+                },
+                None => sheepfile.get_entry_for_name(&name),
+            }.ok_or(Error::MissingFileName(name))?;
             let data = get_entry_data(&cli.sheepfile_path, entry).await?;
             fs::write(&out_path, &data).await?;
             println!("Found {} (name hash {}), wrote {} bytes to {:?}", entry.file_id, entry.name_hash, data.len(), &out_path);
         },
-        Commands::Create { cache_path } => {
+        Commands::List { pattern, listfile_path, check_hashes } => {
+            let sheepfile = new_sheepfile(&cli.sheepfile_path).await?;
+            let listfile = Listfile::parse(&fs::read_to_string(listfile_path).await?)?;
+            for (entry, path) in sheepfile.glob_listfile(&listfile, &pattern) {
+                println!("{};{}", entry.file_id, path);
+            }
+            if check_hashes {
+                let report = sheepfile.check_listfile_name_hashes(&listfile);
+                for mismatch in &report.mismatching {
+                    println!("mismatch: {} \"{}\" hashes to {:016X}, stored hash is {:016X}", mismatch.file_id, mismatch.path, mismatch.path_hash, mismatch.stored_hash);
+                }
+                println!("{} listfile paths match their name hash, {} don't, {} files have no name hash",
+                    report.matching.len(), report.mismatching.len(), report.unhashed.len());
+            }
+        },
+        Commands::Create { cache_path, keys_path, verify, low_memory, locale, require_flag, exclude_flag, download_priority, ribbit, classic_build, era_build } => {
+            let keys = match keys_path {
+                Some(keys_path) => KeyStore::parse(&fs::read_to_string(keys_path).await?)?,
+                None => KeyStore::default(),
+            };
+            info!("loaded {} TACT keys", keys.len());
+            let options = FetcherOptions {
+                paged_encoding: low_memory,
+                locales: locale.iter().map(|name| parse_locale(name)).collect::<Result<_, _>>()?,
+                root_filter: RootEntryFilter {
+                    required_content_flags: parse_content_flags(&require_flag)?,
+                    excluded_content_flags: parse_content_flags(&exclude_flag)?,
+                    ..RootEntryFilter::default()
+                },
+                ribbit: ribbit.map(|server| RibbitClient::new(&server, RibbitVersion::V1)),
+                ..FetcherOptions::default()
+            };
             info!("creating wow_classic CDNFetcher...");
-            let mut classic_fetcher = CDNFetcher::init(&cache_path, PATCH_SERVER, "wow_classic", REGION).await?;
+            let classic_options = FetcherOptions { build: classic_build.unwrap_or_default(), ..options.clone() };
+            let mut classic_fetcher = CDNFetcher::init_with_options(&cache_path, PATCH_SERVER, "wow_classic", REGION, &classic_options).await?;
+            classic_fetcher.keys = keys.clone();
+            classic_fetcher.verify = verify;
+            if let Some(max_priority) = download_priority {
+                restrict_to_download_priority(&mut classic_fetcher, max_priority).await?;
+            }
             info!("creating wow_classic_era CDNFetcher...");
-            let mut era_fetcher = CDNFetcher::init(&cache_path, PATCH_SERVER, "wow_classic_era", REGION).await?;
+            let era_options = FetcherOptions { build: era_build.unwrap_or_default(), ..options };
+            let mut era_fetcher = CDNFetcher::init_with_options(&cache_path, PATCH_SERVER, "wow_classic_era", REGION, &era_options).await?;
+            era_fetcher.keys = keys;
+            era_fetcher.verify = verify;
+            if let Some(max_priority) = download_priority {
+                restrict_to_download_priority(&mut era_fetcher, max_priority).await?;
+            }
+            // enough to pin the same builds again with --classic-build/--era-build
+            for (product, fetcher) in [("wow_classic", &classic_fetcher), ("wow_classic_era", &era_fetcher)] {
+                println!("{}: {} ({}:{})", product, fetcher.build_config.build_name.as_deref().unwrap_or("unnamed build"),
+                    fetcher.build_config_key, fetcher.cdn_config_key);
+            }
             info!("creating sheepfile at {:?}", &cli.sheepfile_path);
             let sheepfile = SheepfileWriter::new(cli.sheepfile_path).await?;
             info!("writing sheepfile contents from fetchers...");
             sheepfile.write_cdn_files(&[&mut classic_fetcher, &mut era_fetcher]).await?;
         },
+        Commands::Footprint { cache_path, product } => {
+            let fetcher = CDNFetcher::init(&cache_path, PATCH_SERVER, &product, REGION).await?;
+            if let Some(install) = fetcher.fetch_install_manifest().await? {
+                for (tag, size) in install.get_tag_sizes() {
+                    println!("install {}: {} bytes", tag, size);
+                }
+            }
+            if let Some(download) = fetcher.fetch_download_manifest().await? {
+                for (tag, size) in download.get_tag_sizes() {
+                    println!("download {}: {} bytes", tag, size);
+                }
+            }
+            if let Some(size) = fetcher.fetch_size_manifest().await? {
+                println!("total size: {} bytes", size.total_size);
+            }
+        },
     }
     Ok(())
 }
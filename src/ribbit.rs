@@ -0,0 +1,257 @@
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::debug;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::error::Error;
+use crate::tact::manifest::Manifest;
+
+pub const RIBBIT_SERVER: &str = "us.version.battle.net:1119";
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RibbitVersion {
+    // responses come wrapped in a signed MIME message with a checksum
+    #[default]
+    V1,
+    // responses are bare BPSV
+    V2,
+}
+
+// A response body, along with the signature it came with (v1 only)
+#[derive(Clone, Debug)]
+pub struct RibbitResponse {
+    pub body: Vec<u8>,
+    // a PKCS#7 signature over the body. We don't check it against
+    // Blizzard's certificate chain, only the message checksum.
+    pub signature: Option<Vec<u8>>,
+}
+
+// A client for Ribbit, Blizzard's TCP protocol for the same versions/cdns
+// manifests the HTTP patch server has. Each request is a single command line,
+// answered with a response and then a closed connection. See
+// https://wowdev.wiki/Ribbit
+#[derive(Clone, Debug)]
+pub struct RibbitClient {
+    // host:port
+    pub server: String,
+    pub version: RibbitVersion,
+    // for connecting, and then separately for reading the response
+    pub timeout: Duration,
+}
+
+impl RibbitClient {
+    pub fn new(server: &str, version: RibbitVersion) -> Self {
+        RibbitClient {
+            server: server.into(),
+            version,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub async fn request(&self, command: &str) -> Result<RibbitResponse, Error> {
+        let version = match self.version {
+            RibbitVersion::V1 => "v1",
+            RibbitVersion::V2 => "v2",
+        };
+        debug!("ribbit: requesting {}/{} from {}", version, command, &self.server);
+        let mut stream = timeout(self.timeout, TcpStream::connect(&self.server)).await
+            .map_err(|_| Error::RibbitTimeout(self.server.clone()))??;
+        stream.write_all(format!("{}/{}\r\n", version, command).as_bytes()).await?;
+        let mut data = Vec::new();
+        timeout(self.timeout, stream.read_to_end(&mut data)).await
+            .map_err(|_| Error::RibbitTimeout(self.server.clone()))??;
+        match self.version {
+            RibbitVersion::V1 => parse_v1_response(&data),
+            RibbitVersion::V2 => Ok(RibbitResponse { body: data, signature: None }),
+        }
+    }
+
+    async fn request_manifest(&self, command: &str) -> Result<Manifest, Error> {
+        Manifest::parse(&self.request(command).await?.body)
+    }
+
+    pub async fn fetch_versions(&self, product: &str) -> Result<Manifest, Error> {
+        self.request_manifest(&format!("products/{}/versions", product)).await
+    }
+
+    pub async fn fetch_cdns(&self, product: &str) -> Result<Manifest, Error> {
+        self.request_manifest(&format!("products/{}/cdns", product)).await
+    }
+
+    // The background downloader's versions, for pre-loading the next build
+    pub async fn fetch_bgdl(&self, product: &str) -> Result<Manifest, Error> {
+        self.request_manifest(&format!("products/{}/bgdl", product)).await
+    }
+
+    // Every product, with the seqn of each of its manifests
+    pub async fn fetch_summary(&self) -> Result<Manifest, Error> {
+        self.request_manifest("summary").await
+    }
+}
+
+// (lowercased name, value) pairs
+type MimeHeaders = Vec<(String, String)>;
+
+// Splits a MIME entity into its headers and body
+fn split_mime_headers(entity: &str) -> Result<(MimeHeaders, &str), Error> {
+    let (headers, body) = entity.split_once("\r\n\r\n")
+        .or_else(|| entity.split_once("\n\n"))
+        .ok_or(Error::InvalidRibbitResponse("MIME entity has no body"))?;
+    let headers = headers.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    Ok((headers, body))
+}
+
+fn get_mime_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter()
+        .find(|(header_name, _)| header_name == name)
+        .map(|(_, value)| value.as_str())
+}
+
+// Parses a v1 response: a multipart MIME message with the BPSV body and its
+// signature as parts, followed by a `Checksum: <sha256>` line covering
+// everything before it
+pub fn parse_v1_response(data: &[u8]) -> Result<RibbitResponse, Error> {
+    let message = std::str::from_utf8(data)
+        .map_err(|_| Error::InvalidRibbitResponse("response isn't UTF-8"))?;
+
+    let checksum_start = message.rfind("Checksum: ")
+        .ok_or(Error::InvalidRibbitResponse("missing checksum"))?;
+    let (message, checksum) = message.split_at(checksum_start);
+    let checksum = checksum["Checksum: ".len()..].trim();
+    let actual_checksum: String = Sha256::digest(message.as_bytes()).iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if !checksum.eq_ignore_ascii_case(&actual_checksum) {
+        return Err(Error::RibbitChecksumMismatch);
+    }
+
+    let (headers, body) = split_mime_headers(message)?;
+    let boundary = get_mime_header(&headers, "content-type")
+        .and_then(|content_type| content_type.split(';').find_map(|param| param.trim().strip_prefix("boundary=")))
+        .map(|boundary| boundary.trim_matches('"'))
+        .ok_or(Error::InvalidRibbitResponse("missing MIME boundary"))?;
+
+    let delimiter = format!("--{}", boundary);
+    let mut response_body = None;
+    let mut signature = None;
+    // the preamble comes before the first delimiter, and the epilogue after
+    // the last one (which is followed by "--")
+    for part in body.split(delimiter.as_str()).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+        // the line break before each delimiter belongs to the delimiter
+        let part = part.strip_suffix("\r\n").or_else(|| part.strip_suffix('\n')).unwrap_or(part);
+        let part = part.strip_prefix("\r\n").or_else(|| part.strip_prefix('\n')).unwrap_or(part);
+        let (part_headers, content) = split_mime_headers(part)?;
+        let is_signature = get_mime_header(&part_headers, "content-type")
+            .is_some_and(|content_type| content_type.starts_with("application/cms"));
+        if is_signature {
+            let encoded: String = content.split_whitespace().collect();
+            signature = Some(BASE64.decode(encoded)
+                .map_err(|_| Error::InvalidRibbitResponse("signature isn't valid base64"))?);
+        } else if response_body.is_none() {
+            response_body = Some(content.as_bytes().to_vec());
+        }
+    }
+
+    Ok(RibbitResponse {
+        body: response_body.ok_or(Error::InvalidRibbitResponse("missing body part"))?,
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+
+    const SUMMARY: &str = "Product!STRING:0|Seqn!DEC:4|Flags!STRING:0\n## seqn = 100\nwow|200|\nwow|201|cdn\nwow_classic|202|\n";
+
+    fn make_v1_response(body: &str) -> String {
+        let mut message = String::new();
+        message.push_str("MIME-Version: 1.0\r\n");
+        message.push_str("Content-Type: multipart/alternative; boundary=\"abcdef\"\r\n\r\n");
+        message.push_str("--abcdef\r\nContent-Type: text/plain\r\nContent-Disposition: summary\r\n\r\n");
+        message.push_str(body);
+        message.push_str("\r\n--abcdef\r\nContent-Type: application/cms\r\nContent-Disposition: attachment; filename=\"summary.sig\"\r\n");
+        message.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+        message.push_str(&BASE64.encode(b"signature"));
+        message.push_str("\r\n--abcdef--\r\n");
+        let checksum: String = Sha256::digest(message.as_bytes()).iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        message.push_str(&format!("Checksum: {}\r\n", checksum));
+        message
+    }
+
+    // Answers a single request with whatever `respond` returns for its command
+    async fn serve_once<F: FnOnce(&str) -> String + Send + 'static>(respond: F) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.split();
+            let mut command = String::new();
+            tokio::io::BufReader::new(reader).read_line(&mut command).await.unwrap();
+            writer.write_all(respond(command.trim_end()).as_bytes()).await.unwrap();
+        });
+        server
+    }
+
+    #[tokio::test]
+    async fn test_ribbit_v1() {
+        let server = serve_once(|command| {
+            assert_eq!(command, "v1/summary");
+            make_v1_response(SUMMARY)
+        }).await;
+        let client = RibbitClient::new(&server, RibbitVersion::V1);
+        let summary = client.fetch_summary().await.unwrap();
+        assert_eq!(summary.seqn, Some(100));
+        assert_eq!(summary.rows.len(), 3);
+        assert_eq!(summary.get_dec_field(summary.find_row("Product", "wow_classic").unwrap(), "Seqn"), Some(202));
+
+        let response = parse_v1_response(make_v1_response(SUMMARY).as_bytes()).unwrap();
+        assert_eq!(response.body, SUMMARY.as_bytes());
+        assert_eq!(response.signature.as_deref(), Some(b"signature".as_slice()));
+
+        let corrupted = make_v1_response(SUMMARY).replace("wow_classic", "wow_clessic");
+        assert!(matches!(parse_v1_response(corrupted.as_bytes()), Err(Error::RibbitChecksumMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_ribbit_timeout() {
+        // accepts the connection, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+        let mut client = RibbitClient::new(&server, RibbitVersion::V2);
+        client.timeout = Duration::from_millis(100);
+        assert!(matches!(client.fetch_summary().await, Err(Error::RibbitTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_ribbit_v2() {
+        let server = serve_once(|command| {
+            assert_eq!(command, "v2/products/wow_classic/versions");
+            "Region!STRING:0|BuildId!DEC:4\n## seqn = 5\nus|54261\n".to_string()
+        }).await;
+        let client = RibbitClient::new(&server, RibbitVersion::V2);
+        let versions = client.fetch_versions("wow_classic").await.unwrap();
+        assert_eq!(versions.seqn, Some(5));
+        assert_eq!(versions.get_dec_field(0, "BuildId"), Some(54261));
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use deku::DekuContainerRead;
 
-use crate::{error::Error, sheepfile::{Entry, Index}};
+use crate::{error::Error, hashpath::hash_path, listfile::{Listfile, NameHashReport}, sheepfile::{Entry, Index}};
 
 
 pub struct SheepfileReader {
@@ -33,14 +33,27 @@ impl SheepfileReader {
     }
 
     pub fn get_entry_for_name(&self, name: &str) -> Option<&Entry> {
-        let normalized = name.to_ascii_uppercase().replace("/", "\\");
-        let mut name_hash = hashers::jenkins::lookup3(normalized.as_bytes());
-        // unsure if our lookup3 is bugged or what, but the high and low values
-        // are swapped
-        let high = name_hash & 0xffffffff00000000;
-        let low = name_hash & 0x00000000ffffffff;
-        name_hash = high >> 32 | low << 32;
-        let index = *self.name_hash_to_entry_index.get(&name_hash)?;
+        let index = *self.name_hash_to_entry_index.get(&hash_path(name))?;
         Some(&self.entries[index])
     }
+
+    // Resolves a real path through the listfile, falling back to its name
+    // hash for files the listfile doesn't know about
+    pub fn get_entry_for_listfile_path(&self, listfile: &Listfile, path: &str) -> Option<&Entry> {
+        listfile.get_file_id_for_path(path)
+            .and_then(|file_id| self.get_entry_for_file_id(file_id))
+            .or_else(|| self.get_entry_for_name(path))
+    }
+
+    // Lists the entries whose listfile path matches a glob pattern
+    pub fn glob_listfile<'a>(&'a self, listfile: &'a Listfile, pattern: &str) -> impl Iterator<Item=(&'a Entry, &'a str)> + 'a {
+        listfile.glob(pattern)
+            .filter_map(|(file_id, path)| Some((self.get_entry_for_file_id(file_id)?, path)))
+    }
+
+    // Sheepfiles store a zero name hash for files without one
+    pub fn check_listfile_name_hashes(&self, listfile: &Listfile) -> NameHashReport {
+        listfile.check_name_hashes(self.entries.iter()
+            .map(|entry| (entry.file_id, (entry.name_hash != 0).then_some(entry.name_hash))))
+    }
 }
@@ -1,13 +1,15 @@
-use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}};
+use std::{collections::{HashMap, HashSet}, io::SeekFrom, path::{Path, PathBuf}};
 
 use deku::DekuContainerWrite;
-use log::{error, info};
-use tokio::{fs::{self, File}, io::AsyncWriteExt};
+use log::{error, info, warn};
+use tokio::{fs::{self, File}, io::{AsyncRead, AsyncSeekExt, AsyncWriteExt}};
 
-use crate::{cdn::CDNFetcher, error::Error, sheepfile::{get_data_filename, Entry, Index, INDEX_FILENAME}, tact::{archive::{ArchiveIndex, ArchiveIndexEntry}, blte::decode_blte}};
+use crate::{cdn::{CDNFetcher, DataLocation}, error::Error, sheepfile::{get_data_filename, Entry, Index, INDEX_FILENAME}, tact::{archive::{ArchiveIndex, ArchiveIndexEntry}, blte::BLTEReader, common::EKey, root::RootEntryFilter}};
 
 const MAX_DATA_FILE_SIZE_BYTES: usize = 256000000;
 
+type PendingEntry<'a> = (u32, u64, EKey, DataLocation<'a>, &'a &'a mut CDNFetcher);
+
 pub struct SheepfileWriter {
     pub path: PathBuf,
     current_data_index: usize,
@@ -29,32 +31,51 @@ impl SheepfileWriter {
         })
     }
 
-    pub async fn write_cdn_files(mut self, cdns: &[&mut CDNFetcher]) -> Result<(), Error> {
-        let mut all_entries: Vec<(u32, u64, &ArchiveIndexEntry, &ArchiveIndex, &&mut CDNFetcher)> = Vec::new();
+    pub async fn write_cdn_files(self, cdns: &[&mut CDNFetcher]) -> Result<(), Error> {
+        self.write_cdn_files_with_filter(cdns, &RootEntryFilter::default()).await
+    }
+
+    // Only writes files with a root entry matching the filter (on top of each
+    // fetcher's own root filter)
+    pub async fn write_cdn_files_with_filter(mut self, cdns: &[&mut CDNFetcher], filter: &RootEntryFilter) -> Result<(), Error> {
+        let mut all_entries: Vec<PendingEntry> = Vec::new();
         let mut all_file_ids = HashSet::new();
         for cdn in cdns {
-            let mut archive_to_entries: HashMap<&str, (&ArchiveIndex, Vec<(u32, u64, &ArchiveIndexEntry, &ArchiveIndex, &&mut CDNFetcher)>)> = HashMap::new();
-            for (&file_id, &index) in cdn.root.file_id_to_entry_index.iter() {
+            let mut archive_to_entries: HashMap<&str, (&ArchiveIndex, Vec<PendingEntry>)> = HashMap::new();
+            for &file_id in cdn.root.file_id_to_entry_indices.keys() {
                 if all_file_ids.contains(&file_id) {
                     continue;
                 }
-                let root_entry = &cdn.root.entries[index];
-                let Some(ekey) = cdn.encoding.get_ekey_for_ckey(&root_entry.ckey) else {
-                    error!("skipping file id {}, couldn't find ekey", file_id);
+                let Some(root_entry) = cdn.root.get_entry_for_file_id_with_filter(file_id, filter) else {
                     continue;
                 };
-                let Some((archive, archive_entry)) = cdn.find_archive_entry(ekey) else {
-                    error!("skipping file id {}, couldn't find archive entry", file_id);
+                if cdn.encoding.get_ekeys_for_ckey(&root_entry.ckey).is_none() {
+                    error!("skipping file id {}, couldn't find ekey", file_id);
+                    continue;
+                }
+                let Some((ekey, location)) = cdn.find_data_location_for_ckey(&root_entry.ckey) else {
+                    error!("skipping file id {}, couldn't find archive entry or encoded size", file_id);
                     continue;
                 };
-                let (_, entries) = archive_to_entries.entry(&archive.key).or_insert((archive, Vec::new()));
-                entries.push((file_id, root_entry.name_hash, archive_entry, &archive, cdn));
+                let entry = (file_id, root_entry.name_hash.unwrap_or(0), ekey, location, cdn);
+                match location {
+                    DataLocation::Archive(archive, _) => {
+                        let (_, entries) = archive_to_entries.entry(&archive.key).or_insert((archive, Vec::new()));
+                        entries.push(entry);
+                    },
+                    DataLocation::Loose(_) => all_entries.push(entry),
+                }
                 all_file_ids.insert(file_id);
             }
 
             let n_archives = archive_to_entries.len();
             for (i, (archive, entries)) in archive_to_entries.into_values().enumerate() {
-                let index_entries: Vec<&ArchiveIndexEntry> = entries.iter().map(|entry| entry.2).collect();
+                let index_entries: Vec<&ArchiveIndexEntry> = entries.iter()
+                    .filter_map(|entry| match entry.3 {
+                        DataLocation::Archive(_, archive_entry) => Some(archive_entry),
+                        DataLocation::Loose(_) => None,
+                    })
+                    .collect();
                 info!("[{}/{}] fetching archive {} (contains {} entries)...", i, n_archives, &archive.key, index_entries.len());
                 let _ = cdn.cache.fetch_archive_entries(&cdn.hosts[0], archive, index_entries.as_slice()).await?;
                 all_entries.extend(entries);
@@ -62,16 +83,34 @@ impl SheepfileWriter {
         }
 
         info!("writing {} fileIDs to sheepfile...", all_entries.len());
-        all_entries.sort_by(|a, b| a.0.cmp(&b.0));
-        for (file_id, name_hash, archive_entry, archive, cdn) in all_entries {
-            let data = cdn.cache.fetch_archive_entry(&cdn.hosts[0], archive, archive_entry).await?;
-            match decode_blte(&data) {
-                Ok(uncompressed_data) => self.append_entry(file_id, name_hash, &uncompressed_data).await?,
-                Err(Error::UnsupportedEncryptedData) => {
-                    info!("file {} contains encrypted data, skipping", file_id);
+        all_entries.sort_by_key(|a| a.0);
+        for (file_id, name_hash, ekey, location, cdn) in all_entries {
+            let data = match cdn.open_data_location(&ekey, location).await {
+                Ok(data) => data,
+                Err(e) if matches!(location, DataLocation::Loose(_)) => {
+                    error!("file {} couldn't be fetched from its loose data file ({}), skipping", file_id, e);
                     continue;
                 },
                 Err(e) => return Err(e),
+            };
+            let result = match cdn.open_blte(&ekey, location.get_encoded_size(), data).await {
+                Ok(mut blte) => self.append_blte_entry(file_id, name_hash, &mut blte).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {},
+                Err(Error::MissingTactKey(key_name)) => {
+                    info!("file {} is encrypted with unknown key {:016X}, skipping", file_id, key_name);
+                    continue;
+                },
+                // I/O errors may be from writing the sheepfile itself, so
+                // they're the only ones that stop the run
+                Err(e @ Error::IOError(_)) => return Err(e),
+                // append_blte_entry has already rolled back anything it wrote
+                Err(e) => {
+                    warn!("file {} couldn't be decoded ({}), skipping", file_id, e);
+                    continue;
+                },
             }
         }
 
@@ -89,11 +128,53 @@ impl SheepfileWriter {
             start_bytes: self.current_data_file_size as u32,
             size_bytes: data.len() as u32,
         });
-        self.current_data_file.write_all(&data).await?;
+        self.current_data_file.write_all(data).await?;
         self.current_data_file_size += data.len();
         Ok(())
     }
 
+    // Decodes a BLTE straight into the current data file. If decoding fails
+    // partway through, the data file is rolled back and the error returned,
+    // leaving the writer able to continue with the next entry.
+    pub async fn append_blte_entry<R: AsyncRead + Unpin>(&mut self, file_id: u32, name_hash: u64, blte: &mut BLTEReader<'_, R>) -> Result<(), Error> {
+        let Some(decoded_size) = blte.get_decoded_size() else {
+            let mut data = Vec::new();
+            while let Some(chunk) = blte.next_chunk_async().await? {
+                data.extend(chunk);
+            }
+            return self.append_entry(file_id, name_hash, &data).await;
+        };
+
+        if decoded_size + self.current_data_file_size > MAX_DATA_FILE_SIZE_BYTES {
+            self.new_data_file().await?;
+        }
+        let start = self.current_data_file_size;
+        let mut size = 0;
+        loop {
+            match blte.next_chunk_async().await {
+                Ok(Some(chunk)) => {
+                    self.current_data_file.write_all(&chunk).await?;
+                    size += chunk.len();
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    self.current_data_file.set_len(start as u64).await?;
+                    self.current_data_file.seek(SeekFrom::Start(start as u64)).await?;
+                    return Err(e);
+                },
+            }
+        }
+        self.entries.push(Entry {
+            file_id,
+            name_hash,
+            data_file_index: self.current_data_index as u16,
+            start_bytes: start as u32,
+            size_bytes: size as u32,
+        });
+        self.current_data_file_size += size;
+        Ok(())
+    }
+
     pub async fn finish(self) -> Result<(), Error> {
         let mut index_file = fs::File::create(self.path.join(INDEX_FILENAME)).await?;
         let index = Index {
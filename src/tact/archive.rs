@@ -2,26 +2,66 @@ use std::collections::HashMap;
 use std::ops::Range;
 
 use deku::{DekuRead, DekuContainerRead};
+use md5::{Digest, Md5};
 
 use crate::{error::Error, tact::common::EKey};
 use crate::tact::common::NULL_EKEY;
 
+const FOOTER_SIZE: usize = 0x1c;
+const BLOCK_SIZE_KB: u8 = 4;
+const CHECKSUM_SIZE: usize = 8;
+// Regular indices store a 4 byte offset, archive-groups prefix it with a 2
+// byte archive number, and file indices (of loose files) have no offset
+const FILE_INDEX_OFFSET_BYTES: u8 = 0;
+const ARCHIVE_OFFSET_BYTES: u8 = 4;
+const ARCHIVE_GROUP_OFFSET_BYTES: u8 = 6;
+
 #[derive(DekuRead, Debug)]
 pub struct ArchiveIndexFooter {
-    pub toc_hash: [u8; 0x10],
-    #[deku(assert_eq = "1")]
+    pub toc_hash: [u8; CHECKSUM_SIZE],
     pub version: u8,
-    #[deku(pad_bytes_before = "2", assert_eq = "4")]
+    #[deku(pad_bytes_before = "2")]
     pub block_size_kb: u8,
-    #[deku(assert_eq = "4")]
     pub offset_bytes: u8,
-    #[deku(assert_eq = "4")]
     pub size_bytes: u8,
-    #[deku(assert_eq = "16")]
     pub key_size_in_bytes: u8,
-    #[deku(assert_eq = "8")]
     pub checksum_size: u8,
+    #[deku(endian = "little")]
     pub num_files: u32,
+    pub footer_hash: [u8; CHECKSUM_SIZE],
+}
+
+impl ArchiveIndexFooter {
+    fn validate(&self, offset_bytes: u8) -> Result<(), Error> {
+        let expected = [
+            ("version", self.version, 1),
+            ("block size", self.block_size_kb, BLOCK_SIZE_KB),
+            ("size field size", self.size_bytes, 4),
+            ("key size", self.key_size_in_bytes, 16),
+            ("checksum size", self.checksum_size, CHECKSUM_SIZE as u8),
+        ];
+        for (field, value, expected_value) in expected {
+            if value != expected_value {
+                return Err(Error::UnsupportedArchiveIndexFooter(field, value));
+            }
+        }
+        if self.offset_bytes != offset_bytes {
+            return Err(Error::UnsupportedArchiveIndexOffsetSize(self.offset_bytes));
+        }
+        Ok(())
+    }
+}
+
+fn truncated_md5(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    Md5::digest(data)[..CHECKSUM_SIZE].try_into().unwrap()
+}
+
+// The footer hash covers the footer from its version onwards, with the hash
+// itself zeroed
+fn get_footer_hash(footer_data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut hashed = footer_data[CHECKSUM_SIZE..FOOTER_SIZE - CHECKSUM_SIZE].to_vec();
+    hashed.extend([0; CHECKSUM_SIZE]);
+    truncated_md5(&hashed)
 }
 
 #[derive(Clone)]
@@ -47,41 +87,78 @@ impl ArchiveIndexEntry {
     }
 }
 
-impl ArchiveIndex {
-    pub fn parse(key: &str, data: &[u8]) -> Result<Self, Error> {
-        let mut entries: HashMap<EKey, ArchiveIndexEntry> = HashMap::new();
-        let footer_offset = data.len() - 0x24;
-        let (_, footer): (_, ArchiveIndexFooter) = ArchiveIndexFooter::from_bytes((&data[footer_offset..], 0))?;
-
-        let block_size = (footer.block_size_kb as usize) << 10;
-        let mut num_files = 0;
-        let mut block_start = 0;
-        loop {
-            let block_end = block_start + block_size;
-            let mut block_data = &data[block_start..block_end];
-            loop {
-                let Ok(((new_block_data, _), entry)) = ArchiveIndexEntry::from_bytes((block_data, 0)) else {
-                    break;
-                };
-
-                block_data = new_block_data;
-                if entry.ekey == NULL_EKEY {
-                    break;
-                }
-
-                entries.insert(entry.ekey.clone(), entry);
-                num_files += 1;
-            }
+#[derive(DekuRead)]
+struct ArchiveGroupIndexEntry {
+    ekey: EKey,
+    #[deku(endian = "big")]
+    size_bytes: u32,
+    #[deku(endian = "big")]
+    archive_index: u16,
+    #[deku(endian = "big")]
+    offset_bytes: u32,
+}
 
-            block_start += block_size;
+// Reads and verifies the footer, table of contents and each block, where
+// `read_entry` parses a single entry's bytes
+fn parse_index<T, F>(data: &[u8], offset_bytes: u8, read_entry: F) -> Result<Vec<T>, Error>
+    where F: Fn(&[u8]) -> Result<(EKey, T), Error>
+{
+    let footer_offset = data.len().checked_sub(FOOTER_SIZE).ok_or(Error::TruncatedArchiveIndex)?;
+    let footer_data = &data[footer_offset..];
+    let (_, footer) = ArchiveIndexFooter::from_bytes((footer_data, 0))?;
+    footer.validate(offset_bytes)?;
+    if get_footer_hash(footer_data) != footer.footer_hash {
+        return Err(Error::ArchiveIndexFooterChecksumMismatch);
+    }
+
+    // each block has a last key and a hash in the table of contents
+    let block_size = (footer.block_size_kb as usize) << 10;
+    let num_blocks = footer_offset / (block_size + 16 + CHECKSUM_SIZE);
+    let toc_start = num_blocks * block_size;
+    if toc_start + num_blocks * (16 + CHECKSUM_SIZE) != footer_offset {
+        return Err(Error::TruncatedArchiveIndex);
+    }
+    let toc = &data[toc_start..footer_offset];
+    if truncated_md5(toc) != footer.toc_hash {
+        return Err(Error::ArchiveIndexTocChecksumMismatch);
+    }
+    let (last_keys, block_hashes) = toc.split_at(num_blocks * 16);
 
-            if num_files >= footer.num_files {
+    let entry_size = 16 + footer.size_bytes as usize + footer.offset_bytes as usize;
+    let mut entries = Vec::with_capacity(footer.num_files as usize);
+    for (i, block) in data[..toc_start].chunks(block_size).enumerate() {
+        if truncated_md5(block) != block_hashes[i * CHECKSUM_SIZE..(i + 1) * CHECKSUM_SIZE] {
+            return Err(Error::ArchiveIndexBlockChecksumMismatch(i));
+        }
+        let mut last_key = NULL_EKEY;
+        for entry_data in block.chunks_exact(entry_size) {
+            let (ekey, entry) = read_entry(entry_data)?;
+            if ekey == NULL_EKEY {
                 break;
             }
+            last_key = ekey;
+            entries.push(entry);
+        }
+        if last_key.0 != last_keys[i * 16..(i + 1) * 16] {
+            return Err(Error::ArchiveIndexTocMismatch(i));
         }
+    }
+
+    if entries.len() != footer.num_files as usize {
+        return Err(Error::ArchiveIndexEntryCountMismatch(footer.num_files, entries.len()));
+    }
+    Ok(entries)
+}
+
+impl ArchiveIndex {
+    pub fn parse(key: &str, data: &[u8]) -> Result<Self, Error> {
+        let entries = parse_index(data, ARCHIVE_OFFSET_BYTES, |entry_data| {
+            let (_, entry) = ArchiveIndexEntry::from_bytes((entry_data, 0))?;
+            Ok((entry.ekey.clone(), entry))
+        })?;
 
         Ok(ArchiveIndex {
-            entries,
+            entries: entries.into_iter().map(|entry| (entry.ekey.clone(), entry)).collect(),
             key: key.into(),
         })
     }
@@ -90,3 +167,266 @@ impl ArchiveIndex {
         self.entries.get(ekey)
     }
 }
+
+#[derive(DekuRead)]
+struct FileIndexEntry {
+    ekey: EKey,
+    #[deku(endian = "big")]
+    size_bytes: u32,
+}
+
+// An index of loose (non-archived) files and their sizes, like the CDN
+// config's `file-index` and `patch-file-index`
+#[derive(Clone)]
+pub struct FileIndex {
+    pub sizes: HashMap<EKey, u32>,
+    pub key: String,
+}
+
+impl FileIndex {
+    pub fn parse(key: &str, data: &[u8]) -> Result<Self, Error> {
+        let entries = parse_index(data, FILE_INDEX_OFFSET_BYTES, |entry_data| {
+            let (_, entry) = FileIndexEntry::from_bytes((entry_data, 0))?;
+            Ok((entry.ekey.clone(), entry))
+        })?;
+
+        Ok(FileIndex {
+            sizes: entries.into_iter().map(|entry| (entry.ekey, entry.size_bytes)).collect(),
+            key: key.into(),
+        })
+    }
+
+    pub fn get_size_for_ekey(&self, ekey: &EKey) -> Option<u32> {
+        self.sizes.get(ekey).copied()
+    }
+}
+
+// An archive-group combines every archive's index into one, with each entry
+// pointing at an archive by its position in the CDN config's `archives` list
+#[derive(Clone)]
+pub struct ArchiveGroupIndex {
+    pub entries: HashMap<EKey, (u16, ArchiveIndexEntry)>,
+    pub key: String,
+}
+
+impl ArchiveGroupIndex {
+    pub fn parse(key: &str, data: &[u8]) -> Result<Self, Error> {
+        let entries = parse_index(data, ARCHIVE_GROUP_OFFSET_BYTES, |entry_data| {
+            let (_, entry) = ArchiveGroupIndexEntry::from_bytes((entry_data, 0))?;
+            Ok((entry.ekey.clone(), entry))
+        })?;
+
+        Ok(ArchiveGroupIndex {
+            entries: entries.into_iter()
+                .map(|entry| (entry.ekey.clone(), (entry.archive_index, ArchiveIndexEntry {
+                    ekey: entry.ekey,
+                    size_bytes: entry.size_bytes,
+                    offset_bytes: entry.offset_bytes,
+                })))
+                .collect(),
+            key: key.into(),
+        })
+    }
+
+    // Builds the group locally from its archives' indices, for when the CDN
+    // doesn't provide one
+    pub fn build(key: &str, archives: &[ArchiveIndex]) -> Self {
+        let mut entries = HashMap::new();
+        for (archive_index, archive) in archives.iter().enumerate() {
+            for (ekey, entry) in &archive.entries {
+                entries.entry(ekey.clone()).or_insert_with(|| (archive_index as u16, entry.clone()));
+            }
+        }
+        ArchiveGroupIndex {
+            entries,
+            key: key.into(),
+        }
+    }
+
+    // Splits the group back into per-archive indices, given the archive keys
+    // in CDN config order
+    pub fn split(&self, archive_keys: &[String]) -> Vec<ArchiveIndex> {
+        let mut archives: Vec<ArchiveIndex> = archive_keys.iter()
+            .map(|key| ArchiveIndex { entries: HashMap::new(), key: key.clone() })
+            .collect();
+        for (archive_index, entry) in self.entries.values() {
+            if let Some(archive) = archives.get_mut(*archive_index as usize) {
+                archive.entries.insert(entry.ekey.clone(), entry.clone());
+            }
+        }
+        archives
+    }
+
+    pub fn get_entry_for_ekey(&self, ekey: &EKey) -> Option<(u16, &ArchiveIndexEntry)> {
+        self.entries.get(ekey).map(|(archive_index, entry)| (*archive_index, entry))
+    }
+
+    // Serializes the group in the CDN's archive-group index format: 4KB blocks
+    // of sorted entries, then a table of contents and the footer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let block_size = (BLOCK_SIZE_KB as usize) << 10;
+        let entry_size = 16 + 4 + ARCHIVE_GROUP_OFFSET_BYTES as usize;
+        let entries_per_block = block_size / entry_size;
+
+        let mut sorted: Vec<(&EKey, &(u16, ArchiveIndexEntry))> = self.entries.iter().collect();
+        sorted.sort_by_key(|(ekey, _)| ekey.0);
+
+        let mut data = Vec::new();
+        let mut last_keys = Vec::new();
+        let mut block_hashes: Vec<u8> = Vec::new();
+        for block in sorted.chunks(entries_per_block) {
+            let block_start = data.len();
+            for (ekey, (archive_index, entry)) in block {
+                data.extend(ekey.0);
+                data.extend(entry.size_bytes.to_be_bytes());
+                data.extend(archive_index.to_be_bytes());
+                data.extend(entry.offset_bytes.to_be_bytes());
+            }
+            data.resize(block_start + block_size, 0);
+            last_keys.extend(block[block.len() - 1].0.0);
+            block_hashes.extend(truncated_md5(&data[block_start..]));
+        }
+
+        let mut toc = last_keys;
+        toc.extend(block_hashes);
+        data.extend(&toc);
+
+        let mut footer = truncated_md5(&toc).to_vec();
+        footer.extend([1, 0, 0, BLOCK_SIZE_KB, ARCHIVE_GROUP_OFFSET_BYTES, 4, 16, CHECKSUM_SIZE as u8]);
+        footer.extend((sorted.len() as u32).to_le_bytes());
+        footer.extend([0; CHECKSUM_SIZE]);
+        let footer_hash = get_footer_hash(&footer);
+        footer[FOOTER_SIZE - CHECKSUM_SIZE..].copy_from_slice(&footer_hash);
+        data.extend(footer);
+        data
+    }
+}
+
+// A consolidated EKey -> archive lookup across every archive index, kept as a
+// single sorted table so it stays compact for products with thousands of
+// archives. Each archive's own index still holds the entries themselves.
+#[derive(Clone, Default)]
+pub struct ArchiveLookupTable {
+    // (ekey, position in the archive list), sorted by ekey
+    ekeys: Vec<([u8; 16], u16)>,
+}
+
+impl ArchiveLookupTable {
+    // Where an EKey is present in several archives, the first one wins
+    pub fn build(archives: &[ArchiveIndex]) -> Self {
+        let mut ekeys: Vec<([u8; 16], u16)> = archives.iter()
+            .enumerate()
+            .flat_map(|(archive_index, archive)| archive.entries.keys().map(move |ekey| (ekey.0, archive_index as u16)))
+            .collect();
+        ekeys.sort_unstable();
+        ekeys.dedup_by_key(|(ekey, _)| *ekey);
+        ekeys.shrink_to_fit();
+        ArchiveLookupTable { ekeys }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ekeys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ekeys.is_empty()
+    }
+
+    pub fn get_archive_index_for_ekey(&self, ekey: &EKey) -> Option<usize> {
+        let i = self.ekeys.binary_search_by_key(&ekey.0, |(ekey, _)| *ekey).ok()?;
+        Some(self.ekeys[i].1 as usize)
+    }
+
+    pub fn find_entry<'a>(&self, archives: &'a [ArchiveIndex], ekey: &EKey) -> Option<(&'a ArchiveIndex, &'a ArchiveIndexEntry)> {
+        let archive = archives.get(self.get_archive_index_for_ekey(ekey)?)?;
+        Some((archive, archive.get_entry_for_ekey(ekey)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_archive(key: &str, ekeys: &[u8]) -> ArchiveIndex {
+        let entries = ekeys.iter()
+            .map(|&i| {
+                let ekey = EKey([i; 16]);
+                (ekey.clone(), ArchiveIndexEntry { ekey, size_bytes: i as u32 * 10, offset_bytes: i as u32 * 100 })
+            })
+            .collect();
+        ArchiveIndex { entries, key: key.into() }
+    }
+
+    #[test]
+    fn test_archive_group_roundtrip() {
+        let archive_keys = vec!["aa".to_string(), "bb".to_string()];
+        let archives = vec![make_archive("aa", &[1, 3]), make_archive("bb", &[2, 4, 5])];
+        let group = ArchiveGroupIndex::build("cc", &archives);
+        let data = group.to_bytes();
+
+        let parsed = ArchiveGroupIndex::parse("cc", &data).unwrap();
+        assert_eq!(parsed.entries.len(), 5);
+        let (archive_index, entry) = parsed.get_entry_for_ekey(&EKey([4; 16])).unwrap();
+        assert_eq!(archive_index, 1);
+        assert_eq!(entry.get_byte_range(), 400..440);
+        assert!(parsed.get_entry_for_ekey(&EKey([6; 16])).is_none());
+
+        let split = parsed.split(&archive_keys);
+        assert_eq!(split[0].entries.len(), 2);
+        assert_eq!(split[1].get_entry_for_ekey(&EKey([5; 16])).unwrap().offset_bytes, 500);
+
+        // regular archive indices have a different offset size
+        assert!(matches!(ArchiveIndex::parse("cc", &data), Err(Error::UnsupportedArchiveIndexOffsetSize(6))));
+    }
+
+    #[test]
+    fn test_archive_group_many_blocks() {
+        let ekeys: Vec<u8> = (1..=255).collect();
+        let group = ArchiveGroupIndex::build("cc", &[make_archive("aa", &ekeys)]);
+        let parsed = ArchiveGroupIndex::parse("cc", &group.to_bytes()).unwrap();
+        assert_eq!(parsed.entries.len(), 255);
+        assert_eq!(parsed.get_entry_for_ekey(&EKey([200; 16])).unwrap().1.size_bytes, 2000);
+    }
+
+    #[test]
+    fn test_archive_lookup_table() {
+        let archives = vec![make_archive("aa", &[3, 1]), make_archive("bb", &[2, 3, 5])];
+        let table = ArchiveLookupTable::build(&archives);
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.get_archive_index_for_ekey(&EKey([3; 16])), Some(0));
+        let (archive, entry) = table.find_entry(&archives, &EKey([5; 16])).unwrap();
+        assert_eq!(archive.key, "bb");
+        assert_eq!(entry.offset_bytes, 500);
+        assert!(table.find_entry(&archives, &EKey([4; 16])).is_none());
+    }
+
+    #[test]
+    fn test_archive_index_verification() {
+        let group = ArchiveGroupIndex::build("cc", &[make_archive("aa", &[1, 2, 3])]);
+        let data = group.to_bytes();
+        let toc_start = 4096;
+        let footer_start = data.len() - FOOTER_SIZE;
+
+        let mut corrupted = data.clone();
+        corrupted[30] ^= 1;
+        assert!(matches!(ArchiveGroupIndex::parse("cc", &corrupted), Err(Error::ArchiveIndexBlockChecksumMismatch(0))));
+
+        let mut corrupted = data.clone();
+        corrupted[toc_start] ^= 1;
+        assert!(matches!(ArchiveGroupIndex::parse("cc", &corrupted), Err(Error::ArchiveIndexTocChecksumMismatch)));
+
+        let mut corrupted = data.clone();
+        corrupted[footer_start + 9] ^= 1;
+        assert!(matches!(ArchiveGroupIndex::parse("cc", &corrupted), Err(Error::ArchiveIndexFooterChecksumMismatch)));
+
+        // a wrong entry count, with the footer hash fixed up to match
+        let mut corrupted = data.clone();
+        corrupted[footer_start + 16] = 4;
+        let footer_hash = get_footer_hash(&corrupted[footer_start..]);
+        corrupted[footer_start + 20..].copy_from_slice(&footer_hash);
+        assert!(matches!(ArchiveGroupIndex::parse("cc", &corrupted), Err(Error::ArchiveIndexEntryCountMismatch(4, 3))));
+
+        assert!(matches!(ArchiveGroupIndex::parse("cc", &data[1..]), Err(Error::TruncatedArchiveIndex)));
+        assert!(matches!(ArchiveGroupIndex::parse("cc", &data[..10]), Err(Error::TruncatedArchiveIndex)));
+    }
+}
@@ -1,7 +1,15 @@
+use std::io::Read;
+use std::ops::Range;
+
 use deku::{DekuRead, DekuContainerRead};
-use miniz_oxide::inflate::decompress_to_vec_zlib;
+use md5::{Digest, Md5};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
 
 use crate::error::Error;
+use crate::tact::common::EKey;
+use crate::tact::espec::{BlockCount, ESpec, ZlibBits};
+use crate::tact::keys::{arc4_apply, salsa20_apply, KeyStore};
 
 #[derive(DekuRead, Debug)]
 pub struct BLTEChunk {
@@ -18,37 +26,560 @@ pub struct BLTEChunk {
 pub struct BLTEHeader {
     #[deku(endian = "big")]
     pub data_offset: u32,
+    #[deku(cond = "*data_offset != 0", default = "0")]
     pub flag: u8,
-    #[deku(endian = "big", bytes = 3)]
+    #[deku(endian = "big", bytes = 3, cond = "*data_offset != 0", default = "0")]
     pub chunk_count: u32,
     #[deku(count = "chunk_count")]
     pub chunks: Vec<BLTEChunk>,
 }
 
+const HEADERLESS_DATA_OFFSET: usize = 8;
+
+impl BLTEHeader {
+    // A BLTE with a data offset of 0 has no chunk table, and its data is a
+    // single frame running to the end of the buffer.
+    pub fn get_chunk_ranges(&self, buf_len: usize) -> Result<Vec<Range<usize>>, Error> {
+        if self.data_offset == 0 {
+            let frame_range = HEADERLESS_DATA_OFFSET..buf_len;
+            return Ok(vec![frame_range]);
+        }
+
+        let mut ranges = Vec::with_capacity(self.chunks.len());
+        let mut data_offs = self.data_offset as usize;
+        for (chunk_index, chunk) in self.chunks.iter().enumerate() {
+            let end = data_offs + chunk.compressed_size as usize;
+            if end > buf_len {
+                return Err(Error::TruncatedBlteChunk(chunk_index));
+            }
+            ranges.push(data_offs..end);
+            data_offs = end;
+        }
+        Ok(ranges)
+    }
+
+    // The bytes an EKey is computed from: the header up to the data offset,
+    // or the whole buffer for headerless BLTEs.
+    pub fn get_ekey_range(&self, buf_len: usize) -> Range<usize> {
+        if self.data_offset == 0 {
+            0..buf_len
+        } else {
+            0..(self.data_offset as usize).min(buf_len)
+        }
+    }
+}
+
+#[derive(DekuRead, Debug)]
+pub struct BLTEEncryptedHeader {
+    #[deku(assert_eq = "8")]
+    pub key_name_size: u8,
+    #[deku(endian = "little")]
+    pub key_name: u64,
+    #[deku(assert = "*iv_size <= 8")]
+    pub iv_size: u8,
+    #[deku(count = "iv_size")]
+    pub iv: Vec<u8>,
+    pub encryption_type: u8,
+}
+
+#[derive(DekuRead, Debug)]
+pub struct BLTELz4Header {
+    #[deku(assert_eq = "1")]
+    pub header_version: u8,
+    #[deku(endian = "big")]
+    pub decompressed_size: u64,
+    pub block_shift: u8,
+}
+
 pub fn decode_blte(buf: &[u8]) -> Result<Vec<u8>, Error> {
+    decode_blte_with_keys(buf, &KeyStore::default())
+}
+
+pub fn decode_blte_with_keys(buf: &[u8], keys: &KeyStore) -> Result<Vec<u8>, Error> {
+    decode_nested_blte(buf, keys, 0)
+}
+
+// How many 'E' and 'F' frames may wrap each other before we give up, since
+// each one recurses
+const MAX_FRAME_DEPTH: usize = 4;
+
+fn decode_nested_blte(buf: &[u8], keys: &KeyStore, depth: usize) -> Result<Vec<u8>, Error> {
     let header = BLTEHeader::from_bytes((buf, 0))?.1;
-    let mut out = Vec::new();
+    let decoded_size: usize = header.chunks.iter().map(|chunk| chunk.uncompressed_size as usize).sum();
+    // the chunk sizes are untrusted, so don't reserve more than the frames
+    // could plausibly decode to
+    let mut out = Vec::with_capacity(decoded_size.min(buf.len().saturating_mul(LZ4_MAX_RATIO)));
 
-    let mut data_offs = header.data_offset as usize;
-    for chunk in &header.chunks {
-        let chunk_buf = &buf[data_offs .. data_offs + (chunk.compressed_size as usize)];
-        let frame_type = chunk_buf[0] as char;
-        let chunk_data = &chunk_buf[1 .. chunk_buf.len()];
-        match frame_type {
-            'N' => out.extend(chunk_data),
-            'Z' => out.extend(decompress_to_vec_zlib(chunk_data).map_err(Error::ZlibError)?),
-            'E' => return Err(Error::UnsupportedEncryptedData),
-            c => panic!("{}", c),
-        }
-        data_offs += chunk.compressed_size as usize;
+    for (chunk_index, range) in header.get_chunk_ranges(buf.len())?.into_iter().enumerate() {
+        let max_size = header.chunks.get(chunk_index).map(|chunk| chunk.uncompressed_size as usize);
+        decode_frame(&buf[range], chunk_index, max_size, keys, depth, &mut out)?;
     }
 
     Ok(out)
 }
 
+// Checks each chunk's data against its stored MD5, and if an EKey is given,
+// that the BLTE header hashes to it. This doesn't decode anything, so callers
+// opt in by running it before decode_blte.
+pub fn verify_blte(buf: &[u8], ekey: Option<&EKey>) -> Result<(), Error> {
+    let header = BLTEHeader::from_bytes((buf, 0))?.1;
+
+    if let Some(ekey) = ekey {
+        let digest: [u8; 16] = Md5::digest(&buf[header.get_ekey_range(buf.len())]).into();
+        if digest != ekey.0 {
+            return Err(Error::BlteEKeyMismatch(ekey.to_string()));
+        }
+    }
+
+    let ranges = header.get_chunk_ranges(buf.len())?;
+    for (chunk_index, (chunk, range)) in header.chunks.iter().zip(ranges).enumerate() {
+        let digest: [u8; 16] = Md5::digest(&buf[range]).into();
+        if digest != chunk.checksum {
+            return Err(Error::BlteChunkChecksumMismatch(chunk_index));
+        }
+    }
+
+    Ok(())
+}
+
+const DEFAULT_ZLIB_LEVEL: u8 = 9;
+// miniz always compresses with a 32KB window
+const ZLIB_WINDOW_BITS: u8 = 15;
+
+// Splits data into chunks according to an ESpec, pairing each chunk with the
+// ESpec of the frame it'll be encoded as.
+fn get_chunk_specs(data_len: usize, espec: &ESpec) -> Result<Vec<(Range<usize>, &ESpec)>, Error> {
+    let ESpec::Blocks(blocks) = espec else {
+        return Ok(vec![(0..data_len, espec)]);
+    };
+    let invalid = || Error::InvalidESpec(espec.to_string());
+
+    let mut chunks = Vec::new();
+    let mut offs = 0;
+    for block in blocks {
+        if offs >= data_len {
+            break;
+        }
+        let Some(size) = block.size else {
+            chunks.push((offs..data_len, &block.spec));
+            offs = data_len;
+            continue;
+        };
+        if size == 0 {
+            return Err(invalid());
+        }
+        let mut push_chunk = |offs: &mut usize| {
+            let end = (*offs + size).min(data_len);
+            chunks.push((*offs..end, &block.spec));
+            *offs = end;
+        };
+        match block.count {
+            BlockCount::Once => push_chunk(&mut offs),
+            BlockCount::Times(n) => {
+                for _ in 0..n {
+                    if offs >= data_len {
+                        break;
+                    }
+                    push_chunk(&mut offs);
+                }
+            },
+            BlockCount::UntilEnd => {
+                while offs < data_len {
+                    push_chunk(&mut offs);
+                }
+            },
+        }
+    }
+
+    if offs < data_len {
+        return Err(invalid());
+    }
+    if chunks.is_empty() {
+        let first_spec = blocks.first().map(|block| &block.spec).ok_or_else(invalid)?;
+        chunks.push((0..0, first_spec));
+    }
+    Ok(chunks)
+}
+
+fn encode_frame(data: &[u8], espec: &ESpec) -> Result<Vec<u8>, Error> {
+    let mut frame = Vec::with_capacity(data.len() + 1);
+    match espec {
+        ESpec::None => {
+            frame.push(b'N');
+            frame.extend(data);
+        },
+        ESpec::Zlib { level, bits } => {
+            if !matches!(bits, None | Some(ZlibBits::Window(ZLIB_WINDOW_BITS))) {
+                return Err(Error::InvalidESpec(espec.to_string()));
+            }
+            frame.push(b'Z');
+            frame.extend(compress_to_vec_zlib(data, level.unwrap_or(DEFAULT_ZLIB_LEVEL)));
+        },
+        _ => return Err(Error::InvalidESpec(espec.to_string())),
+    }
+    Ok(frame)
+}
+
+// Encodes data into a BLTE with the chunking and compression described by the
+// ESpec, returning its EKey alongside it. The output always has a chunk
+// table, even for single-frame ESpecs like `z`.
+pub fn encode_blte(data: &[u8], espec: &ESpec) -> Result<(EKey, Vec<u8>), Error> {
+    let mut chunks = Vec::new();
+    for (range, chunk_spec) in get_chunk_specs(data.len(), espec)? {
+        let uncompressed_size = range.len() as u32;
+        chunks.push((uncompressed_size, encode_frame(&data[range], chunk_spec)?));
+    }
+
+    let header_size = 8 + 4 + 24 * chunks.len();
+    let mut out = Vec::with_capacity(header_size + chunks.iter().map(|(_, frame)| frame.len()).sum::<usize>());
+    out.extend(b"BLTE");
+    out.extend((header_size as u32).to_be_bytes());
+    out.push(0x0f);
+    out.extend(&(chunks.len() as u32).to_be_bytes()[1..]);
+    for (uncompressed_size, frame) in &chunks {
+        out.extend((frame.len() as u32).to_be_bytes());
+        out.extend(uncompressed_size.to_be_bytes());
+        out.extend(Md5::digest(frame));
+    }
+    let ekey = EKey(Md5::digest(&out).into());
+    for (_, frame) in chunks {
+        out.extend(frame);
+    }
+    Ok((ekey, out))
+}
+
+// Wraps data in a headerless BLTE with a single uncompressed frame, so tests
+// of BLTE encoded formats don't depend on the encoder
+#[cfg(test)]
+pub(crate) fn blte_wrap(data: &[u8]) -> Vec<u8> {
+    let mut blte = b"BLTE".to_vec();
+    blte.extend(0u32.to_be_bytes());
+    blte.push(b'N');
+    blte.extend(data);
+    blte
+}
+
+// Incrementally decodes a BLTE from a reader, yielding one decoded chunk at a
+// time so that neither the full compressed nor decompressed data needs to be
+// held in memory.
+pub struct BLTEReader<'a, R> {
+    reader: R,
+    pub header: BLTEHeader,
+    header_bytes: Vec<u8>,
+    keys: &'a KeyStore,
+    next_chunk_index: usize,
+    pub verify: bool,
+    // for headerless BLTEs, the EKey to check once their frame is read
+    pending_ekey: Option<EKey>,
+}
+
+impl<'a, R> BLTEReader<'a, R> {
+    fn from_header_bytes(reader: R, header_bytes: Vec<u8>, keys: &'a KeyStore) -> Result<Self, Error> {
+        let header = BLTEHeader::from_bytes((&header_bytes, 0))?.1;
+        Ok(BLTEReader {
+            reader,
+            header,
+            header_bytes,
+            keys,
+            next_chunk_index: 0,
+            verify: false,
+            pending_ekey: None,
+        })
+    }
+
+    // Total decoded size according to the chunk table. Headerless BLTEs don't
+    // declare one.
+    pub fn get_decoded_size(&self) -> Option<usize> {
+        if self.header.data_offset == 0 {
+            return None;
+        }
+        Some(self.header.chunks.iter().map(|chunk| chunk.uncompressed_size as usize).sum())
+    }
+
+    // Headerless BLTEs hash their entire contents, so for them the check is
+    // deferred until their frame is read, failing that read on a mismatch.
+    pub fn verify_ekey(&mut self, ekey: &EKey) -> Result<(), Error> {
+        if self.header.data_offset == 0 {
+            self.pending_ekey = Some(ekey.clone());
+            return Ok(());
+        }
+        let digest: [u8; 16] = Md5::digest(&self.header_bytes).into();
+        if digest != ekey.0 {
+            return Err(Error::BlteEKeyMismatch(ekey.to_string()));
+        }
+        Ok(())
+    }
+
+    // Checks the chunk table against the BLTE's actual encoded size (e.g. its
+    // archive entry's), so that bogus chunk sizes fail up front
+    pub fn check_encoded_size(&self, encoded_size: usize) -> Result<(), Error> {
+        let mut remaining = encoded_size.checked_sub(self.header_bytes.len())
+            .ok_or(Error::TruncatedBlteChunk(0))?;
+        for (i, chunk) in self.header.chunks.iter().enumerate() {
+            remaining = remaining.checked_sub(chunk.compressed_size as usize)
+                .ok_or(Error::TruncatedBlteChunk(i))?;
+        }
+        Ok(())
+    }
+
+    fn is_finished(&self) -> bool {
+        let chunk_count = if self.header.data_offset == 0 { 1 } else { self.header.chunks.len() };
+        self.next_chunk_index >= chunk_count
+    }
+
+    fn get_next_chunk_size(&self) -> Option<usize> {
+        self.header.chunks.get(self.next_chunk_index).map(|chunk| chunk.compressed_size as usize)
+    }
+
+    fn decode_next_chunk(&mut self, frame: &[u8]) -> Result<Vec<u8>, Error> {
+        let chunk_index = self.next_chunk_index;
+        self.next_chunk_index += 1;
+        if let Some(ekey) = self.pending_ekey.take() {
+            let digest: [u8; 16] = Md5::new().chain_update(&self.header_bytes).chain_update(frame).finalize().into();
+            if digest != ekey.0 {
+                return Err(Error::BlteEKeyMismatch(ekey.to_string()));
+            }
+        }
+        if self.verify {
+            if let Some(chunk) = self.header.chunks.get(chunk_index) {
+                let digest: [u8; 16] = Md5::digest(frame).into();
+                if digest != chunk.checksum {
+                    return Err(Error::BlteChunkChecksumMismatch(chunk_index));
+                }
+            }
+        }
+        let max_size = self.header.chunks.get(chunk_index).map(|chunk| chunk.uncompressed_size as usize);
+        let mut out = Vec::new();
+        decode_frame(frame, chunk_index, max_size, self.keys, 0, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn get_data_offset(header_start: &[u8]) -> usize {
+    u32::from_be_bytes([header_start[4], header_start[5], header_start[6], header_start[7]]) as usize
+}
+
+// Given the start of a header (its first 8 bytes, or 12 when it has a chunk
+// table), the size of the whole header. The data offset isn't trusted past
+// what the chunk count allows for.
+fn get_header_size(header_start: &[u8]) -> Result<usize, Error> {
+    let data_offset = get_data_offset(header_start);
+    if data_offset == 0 {
+        return Ok(HEADERLESS_DATA_OFFSET);
+    }
+    let chunk_count = u32::from_be_bytes([0, header_start[9], header_start[10], header_start[11]]) as usize;
+    if data_offset < header_start.len() || data_offset > 8 + 4 + 24 * chunk_count {
+        return Err(Error::InvalidBlteDataOffset(data_offset));
+    }
+    Ok(data_offset)
+}
+
+impl<'a, R: Read> BLTEReader<'a, R> {
+    pub fn from_reader(mut reader: R, keys: &'a KeyStore) -> Result<Self, Error> {
+        let mut header_bytes = vec![0; 8];
+        reader.read_exact(&mut header_bytes)?;
+        if get_data_offset(&header_bytes) != 0 {
+            header_bytes.resize(12, 0);
+            reader.read_exact(&mut header_bytes[8..])?;
+        }
+        let start = header_bytes.len();
+        header_bytes.resize(get_header_size(&header_bytes)?, 0);
+        reader.read_exact(&mut header_bytes[start..])?;
+        Self::from_header_bytes(reader, header_bytes, keys)
+    }
+
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.is_finished() {
+            return Ok(None);
+        }
+        let mut frame = Vec::new();
+        match self.get_next_chunk_size() {
+            Some(size) => {
+                // the declared size isn't trusted, so rather than allocating
+                // it up front, only read what the reader actually has
+                (&mut self.reader).take(size as u64).read_to_end(&mut frame)?;
+                if frame.len() < size {
+                    return Err(Error::TruncatedBlteChunk(self.next_chunk_index));
+                }
+            },
+            None => {
+                self.reader.read_to_end(&mut frame)?;
+            },
+        }
+        self.decode_next_chunk(&frame).map(Some)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<'a, R: tokio::io::AsyncRead + Unpin> BLTEReader<'a, R> {
+    pub async fn from_async_reader(mut reader: R, keys: &'a KeyStore) -> Result<Self, Error> {
+        use tokio::io::AsyncReadExt;
+        let mut header_bytes = vec![0; 8];
+        reader.read_exact(&mut header_bytes).await?;
+        if get_data_offset(&header_bytes) != 0 {
+            header_bytes.resize(12, 0);
+            reader.read_exact(&mut header_bytes[8..]).await?;
+        }
+        let start = header_bytes.len();
+        header_bytes.resize(get_header_size(&header_bytes)?, 0);
+        reader.read_exact(&mut header_bytes[start..]).await?;
+        Self::from_header_bytes(reader, header_bytes, keys)
+    }
+
+    pub async fn next_chunk_async(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        use tokio::io::AsyncReadExt;
+        if self.is_finished() {
+            return Ok(None);
+        }
+        let mut frame = Vec::new();
+        match self.get_next_chunk_size() {
+            Some(size) => {
+                (&mut self.reader).take(size as u64).read_to_end(&mut frame).await?;
+                if frame.len() < size {
+                    return Err(Error::TruncatedBlteChunk(self.next_chunk_index));
+                }
+            },
+            None => {
+                self.reader.read_to_end(&mut frame).await?;
+            },
+        }
+        self.decode_next_chunk(&frame).map(Some)
+    }
+}
+
+// LZ4 can't compress better than this, which bounds the decoded size of
+// frames in headerless BLTEs, since they don't declare one
+const LZ4_MAX_RATIO: usize = 255;
+
+// Deflate can't do better than ~1032:1
+const ZLIB_MAX_RATIO: usize = 1032;
+
+// Decodes one LZ4 block which decompresses to exactly `size` bytes, returning
+// how many input bytes it used. Blocks don't store their compressed size, but
+// a block's last sequence is always literals only, so it ends where the output
+// is full. Matches can only refer back within the same block.
+fn decompress_lz4_block(input: &[u8], size: usize, out: &mut Vec<u8>) -> Option<usize> {
+    let start = out.len();
+    let end = start + size;
+    let mut pos = 0;
+    let read_length = |pos: &mut usize, mut length: usize| -> Option<usize> {
+        if length == 15 {
+            loop {
+                let b = *input.get(*pos)?;
+                *pos += 1;
+                length += b as usize;
+                if b != 255 {
+                    break;
+                }
+            }
+        }
+        Some(length)
+    };
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+        let literal_len = read_length(&mut pos, (token >> 4) as usize)?;
+        let literals = input.get(pos..pos.checked_add(literal_len)?)?;
+        if out.len() + literal_len > end {
+            return None;
+        }
+        out.extend_from_slice(literals);
+        pos += literal_len;
+        if out.len() == end {
+            return Some(pos);
+        }
+
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+        let match_len = read_length(&mut pos, (token & 0xf) as usize)? + 4;
+        if offset == 0 || offset > out.len() - start || out.len() + match_len > end {
+            return None;
+        }
+        let match_start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[match_start + i]);
+        }
+    }
+}
+
+// LZ4 frames hold a sequence of independently compressed blocks, each
+// decompressing to `1 << block_shift` bytes (bar the last)
+fn decode_lz4_frame(frame_data: &[u8], chunk_index: usize, max_size: Option<usize>, out: &mut Vec<u8>) -> Result<(), Error> {
+    let ((mut rest, _), header) = BLTELz4Header::from_bytes((frame_data, 0))?;
+    let max_size = max_size.unwrap_or(rest.len().saturating_mul(LZ4_MAX_RATIO));
+    let block_size = 1usize.checked_shl(header.block_shift as u32)
+        .ok_or(Error::InvalidLz4Frame(chunk_index))?;
+    if header.decompressed_size > max_size as u64 {
+        return Err(Error::InvalidLz4Frame(chunk_index));
+    }
+
+    let mut remaining = header.decompressed_size as usize;
+    out.reserve(remaining);
+    while remaining > 0 {
+        let size = remaining.min(block_size);
+        let used = decompress_lz4_block(rest, size, out).ok_or(Error::InvalidLz4Frame(chunk_index))?;
+        rest = &rest[used..];
+        remaining -= size;
+    }
+    Ok(())
+}
+
+// `max_size` is the chunk's declared decoded size, if it has one
+fn decode_frame(frame: &[u8], chunk_index: usize, max_size: Option<usize>, keys: &KeyStore, depth: usize, out: &mut Vec<u8>) -> Result<(), Error> {
+    if depth > MAX_FRAME_DEPTH {
+        return Err(Error::BlteFramesTooDeep(chunk_index));
+    }
+    let Some(&frame_type) = frame.first() else {
+        return Err(Error::TruncatedBlteChunk(chunk_index));
+    };
+    let frame_type = frame_type as char;
+    let frame_data = &frame[1 .. frame.len()];
+    match frame_type {
+        'N' => out.extend(frame_data),
+        'Z' => {
+            let max_size = max_size.unwrap_or(frame_data.len().saturating_mul(ZLIB_MAX_RATIO));
+            out.extend(decompress_to_vec_zlib_with_limit(frame_data, max_size).map_err(Error::ZlibError)?);
+        },
+        'E' => {
+            let decrypted = decrypt_frame(frame_data, chunk_index, keys)?;
+            decode_frame(&decrypted, chunk_index, max_size, keys, depth + 1, out)?;
+        },
+        'F' => out.extend(decode_nested_blte(frame_data, keys, depth + 1)?),
+        '4' => decode_lz4_frame(frame_data, chunk_index, max_size, out)?,
+        c => return Err(Error::UnknownBlteFrame(c)),
+    }
+    Ok(())
+}
+
+// Encrypted frames wrap another frame (N, Z, etc). The IV is extended to 8
+// bytes and its first 4 bytes are XORed with the chunk's index in the BLTE.
+fn decrypt_frame(frame_data: &[u8], chunk_index: usize, keys: &KeyStore) -> Result<Vec<u8>, Error> {
+    let ((rest, _), header) = BLTEEncryptedHeader::from_bytes((frame_data, 0))?;
+    let key = keys.get(header.key_name).ok_or(Error::MissingTactKey(header.key_name))?;
+
+    let mut iv = [0; 8];
+    iv[..header.iv.len()].copy_from_slice(&header.iv);
+    for (i, b) in (chunk_index as u32).to_le_bytes().iter().enumerate() {
+        iv[i] ^= b;
+    }
+
+    let mut data = rest.to_vec();
+    match header.encryption_type as char {
+        'S' => salsa20_apply(key, &iv, &mut data),
+        'A' => {
+            let mut arc4_key = key.to_vec();
+            arc4_key.extend(&iv[..header.iv.len()]);
+            arc4_apply(&arc4_key, &mut data);
+        },
+        c => return Err(Error::UnsupportedEncryptionType(c)),
+    }
+    Ok(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tact::common::NULL_EKEY;
 
     #[test]
     fn test_blte_decode() {
@@ -57,4 +588,303 @@ mod tests {
         let buf = decode_blte(&test_file).unwrap();
         dbg!(buf);
     }
+
+    // An 'E' frame around an 'N' frame. The frame stores `iv`, but the data is
+    // encrypted with `chunk_iv`, which is what the IV should become once the
+    // chunk index is mixed in.
+    fn make_encrypted_frame(key_name: u64, key: &[u8; 16], encryption_type: u8, iv: [u8; 4], chunk_iv: [u8; 4], plaintext: &[u8]) -> Vec<u8> {
+        let mut inner = vec![b'N'];
+        inner.extend(plaintext);
+        if encryption_type == b'S' {
+            let mut nonce = [0; 8];
+            nonce[..4].copy_from_slice(&chunk_iv);
+            salsa20_apply(key, &nonce, &mut inner);
+        } else {
+            let mut arc4_key = key.to_vec();
+            arc4_key.extend(chunk_iv);
+            arc4_apply(&arc4_key, &mut inner);
+        }
+
+        let mut frame = vec![b'E', 8];
+        frame.extend(key_name.to_le_bytes());
+        frame.push(iv.len() as u8);
+        frame.extend(iv);
+        frame.push(encryption_type);
+        frame.extend(inner);
+        frame
+    }
+
+    fn make_encrypted_blte(key_name: u64, key: &[u8; 16], plaintext: &[u8]) -> Vec<u8> {
+        let iv = [0x11, 0x22, 0x33, 0x44];
+        let frame = make_encrypted_frame(key_name, key, b'S', iv, iv, plaintext);
+        make_single_chunk_blte(&frame, plaintext.len())
+    }
+
+    // (frame, uncompressed size) for each chunk
+    fn make_multi_chunk_blte(chunks: &[(Vec<u8>, usize)]) -> Vec<u8> {
+        let mut buf = b"BLTE".to_vec();
+        buf.extend((8 + 4 + 24 * chunks.len() as u32).to_be_bytes());
+        buf.push(0x0f);
+        buf.extend(&(chunks.len() as u32).to_be_bytes()[1..]);
+        for (frame, uncompressed_size) in chunks {
+            buf.extend((frame.len() as u32).to_be_bytes());
+            buf.extend((*uncompressed_size as u32).to_be_bytes());
+            buf.extend(Md5::digest(frame));
+        }
+        for (frame, _) in chunks {
+            buf.extend(frame);
+        }
+        buf
+    }
+
+    fn make_single_chunk_blte(frame: &[u8], uncompressed_size: usize) -> Vec<u8> {
+        make_multi_chunk_blte(&[(frame.to_vec(), uncompressed_size)])
+    }
+
+    #[test]
+    fn test_blte_decode_encrypted() {
+        let key = [0x42; 16];
+        let blte = make_encrypted_blte(0xFA505078126ACB3E, &key, b"hello, salsa");
+
+        match decode_blte(&blte) {
+            Err(Error::MissingTactKey(name)) => assert_eq!(name, 0xFA505078126ACB3E),
+            other => panic!("expected a missing key error, got {:?}", other),
+        }
+
+        let mut keys = KeyStore::default();
+        keys.insert(0xFA505078126ACB3E, key);
+        assert_eq!(decode_blte_with_keys(&blte, &keys).unwrap(), b"hello, salsa");
+    }
+
+    #[test]
+    fn test_blte_decode_encrypted_chunks() {
+        let key_name = 0xFA505078126ACB3E;
+        let key = [0x42; 16];
+        let mut keys = KeyStore::default();
+        keys.insert(key_name, key);
+
+        // each chunk's index is XORed into the start of the IV
+        let iv = [0x11, 0x22, 0x33, 0x44];
+        let chunks = vec![
+            (make_encrypted_frame(key_name, &key, b'S', iv, [0x11, 0x22, 0x33, 0x44], b"chunk 0,"), 8),
+            (make_encrypted_frame(key_name, &key, b'S', iv, [0x10, 0x22, 0x33, 0x44], b"chunk 1,"), 8),
+            (make_encrypted_frame(key_name, &key, b'A', iv, [0x13, 0x22, 0x33, 0x44], b"chunk 2"), 7),
+        ];
+        let blte = make_multi_chunk_blte(&chunks);
+        assert_eq!(decode_blte_with_keys(&blte, &keys).unwrap(), b"chunk 0,chunk 1,chunk 2");
+
+        let mut reader = BLTEReader::from_reader(blte.as_slice(), &keys).unwrap();
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), b"chunk 0,");
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), b"chunk 1,");
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), b"chunk 2");
+
+        // without the chunk index mixed in, later chunks don't decrypt
+        for encryption_type in [b'S', b'A'] {
+            let chunks = vec![
+                (b"Nchunk 0,".to_vec(), 8),
+                (make_encrypted_frame(key_name, &key, encryption_type, iv, iv, b"chunk 1"), 7),
+            ];
+            let decoded = decode_blte_with_keys(&make_multi_chunk_blte(&chunks), &keys);
+            assert!(decoded.is_err() || decoded.unwrap() != b"chunk 0,chunk 1");
+        }
+    }
+
+    #[test]
+    fn test_blte_decode_nested_and_lz4() {
+        let plaintext = b"polymorph polymorph polymorph polymorph";
+        let mut lz4_frame = vec![b'4', 1];
+        lz4_frame.extend((plaintext.len() as u64).to_be_bytes());
+        lz4_frame.push(16);
+        lz4_frame.extend(lz4_flex::block::compress(plaintext));
+        let inner = make_single_chunk_blte(&lz4_frame, plaintext.len());
+
+        let mut nested_frame = vec![b'F'];
+        nested_frame.extend(&inner);
+        let outer = make_single_chunk_blte(&nested_frame, plaintext.len());
+        assert_eq!(decode_blte(&outer).unwrap(), plaintext);
+
+        // frames nested too deeply are rejected rather than recursed into
+        let mut blte = make_single_chunk_blte(b"Nhello", 5);
+        for depth in 0..=MAX_FRAME_DEPTH {
+            let mut frame = vec![b'F'];
+            frame.extend(&blte);
+            blte = make_single_chunk_blte(&frame, 5);
+            let result = decode_blte(&blte);
+            if depth < MAX_FRAME_DEPTH {
+                assert_eq!(result.unwrap(), b"hello");
+            } else {
+                assert!(matches!(result, Err(Error::BlteFramesTooDeep(0))));
+            }
+        }
+
+        let unknown = make_single_chunk_blte(b"Qwhat", 4);
+        assert!(matches!(decode_blte(&unknown), Err(Error::UnknownBlteFrame('Q'))));
+    }
+
+    fn make_lz4_frame(plaintext: &[u8], decompressed_size: u64, block_shift: u8) -> Vec<u8> {
+        let mut frame = vec![b'4', 1];
+        frame.extend(decompressed_size.to_be_bytes());
+        frame.push(block_shift);
+        for block in plaintext.chunks(1 << block_shift) {
+            frame.extend(lz4_flex::block::compress(block));
+        }
+        frame
+    }
+
+    #[test]
+    fn test_blte_decode_lz4_blocks() {
+        // 3.5 blocks of 1KB, with repeats so that there are matches
+        let plaintext: Vec<u8> = (0..3584u32).map(|i| (i % 100) as u8 ^ (i / 700) as u8).collect();
+        let frame = make_lz4_frame(&plaintext, plaintext.len() as u64, 10);
+        assert_eq!(decode_blte(&make_single_chunk_blte(&frame, plaintext.len())).unwrap(), plaintext);
+
+        let mut headerless = b"BLTE".to_vec();
+        headerless.extend(0u32.to_be_bytes());
+        headerless.extend(&frame);
+        assert_eq!(decode_blte(&headerless).unwrap(), plaintext);
+
+        // decoded sizes past the chunk's declared size are rejected up front
+        let oversized = make_lz4_frame(&plaintext, u64::MAX, 10);
+        assert!(matches!(decode_blte(&make_single_chunk_blte(&oversized, plaintext.len())), Err(Error::InvalidLz4Frame(0))));
+        let mut headerless = b"BLTE".to_vec();
+        headerless.extend(0u32.to_be_bytes());
+        headerless.extend(&oversized);
+        assert!(matches!(decode_blte(&headerless), Err(Error::InvalidLz4Frame(0))));
+
+        // a truncated last block
+        let truncated = &frame[..frame.len() - 1];
+        assert!(matches!(decode_blte(&make_single_chunk_blte(truncated, plaintext.len())), Err(Error::InvalidLz4Frame(0))));
+    }
+
+    #[test]
+    fn test_blte_decode_zlib_limit() {
+        let plaintext = vec![0u8; 4096];
+        let mut frame = b"Z".to_vec();
+        frame.extend(compress_to_vec_zlib(&plaintext, 6));
+        assert_eq!(decode_blte(&make_single_chunk_blte(&frame, plaintext.len())).unwrap(), plaintext);
+
+        // zlib output past the chunk's declared size is rejected
+        assert!(matches!(decode_blte(&make_single_chunk_blte(&frame, 100)), Err(Error::ZlibError(_))));
+
+        // a huge declared size doesn't get preallocated
+        assert_eq!(decode_blte(&make_single_chunk_blte(&frame, u32::MAX as usize)).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_blte_verify() {
+        let mut blte = make_single_chunk_blte(b"Nsome data", 9);
+        let ekey = EKey(Md5::digest(&blte[..36]).into());
+        verify_blte(&blte, Some(&ekey)).unwrap();
+        assert!(matches!(verify_blte(&blte, Some(&NULL_EKEY)), Err(Error::BlteEKeyMismatch(_))));
+
+        let last = blte.len() - 1;
+        blte[last] ^= 0xff;
+        assert!(matches!(verify_blte(&blte, None), Err(Error::BlteChunkChecksumMismatch(0))));
+
+        blte.pop();
+        assert!(matches!(verify_blte(&blte, None), Err(Error::TruncatedBlteChunk(0))));
+    }
+
+    #[test]
+    fn test_blte_decode_headerless() {
+        let mut blte = b"BLTE".to_vec();
+        blte.extend(0u32.to_be_bytes());
+        blte.extend(b"Nheaderless");
+        assert_eq!(decode_blte(&blte).unwrap(), b"headerless");
+        assert_eq!(blte_wrap(b"headerless"), blte);
+        let ekey = EKey(Md5::digest(&blte).into());
+        verify_blte(&blte, Some(&ekey)).unwrap();
+    }
+
+    #[test]
+    fn test_blte_reader() {
+        let mut blte = b"BLTE".to_vec();
+        blte.extend((8u32 + 4 + 24 * 2).to_be_bytes());
+        blte.push(0x0f);
+        blte.extend(&2u32.to_be_bytes()[1..]);
+        for frame in [&b"Nfirst,"[..], &b"Nsecond"[..]] {
+            blte.extend((frame.len() as u32).to_be_bytes());
+            blte.extend((frame.len() as u32 - 1).to_be_bytes());
+            blte.extend(Md5::digest(frame));
+        }
+        blte.extend(b"Nfirst,Nsecond");
+
+        let keys = KeyStore::default();
+        let mut reader = BLTEReader::from_reader(blte.as_slice(), &keys).unwrap();
+        reader.verify = true;
+        reader.verify_ekey(&EKey(Md5::digest(&blte[..60]).into())).unwrap();
+        assert_eq!(reader.get_decoded_size(), Some(12));
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), b"first,");
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), b"second");
+        assert!(reader.next_chunk().unwrap().is_none());
+
+        let mut reader = BLTEReader::from_reader(&blte[..blte.len() - 1], &keys).unwrap();
+        reader.next_chunk().unwrap();
+        assert!(matches!(reader.next_chunk(), Err(Error::TruncatedBlteChunk(1))));
+
+        // chunk sizes past the end of the data are caught up front, given the
+        // encoded size, and otherwise without allocating them
+        let reader = BLTEReader::from_reader(blte.as_slice(), &keys).unwrap();
+        reader.check_encoded_size(blte.len()).unwrap();
+        assert!(matches!(reader.check_encoded_size(blte.len() - 1), Err(Error::TruncatedBlteChunk(1))));
+        assert!(matches!(reader.check_encoded_size(30), Err(Error::TruncatedBlteChunk(0))));
+        let mut huge_chunk = blte.clone();
+        huge_chunk[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = BLTEReader::from_reader(huge_chunk.as_slice(), &keys).unwrap();
+        assert!(matches!(reader.next_chunk(), Err(Error::TruncatedBlteChunk(0))));
+
+        // read errors other than running out of data aren't truncation
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("connection reset"))
+            }
+        }
+        let mut reader = BLTEReader::from_reader(blte[..60].chain(FailingReader), &keys).unwrap();
+        assert!(matches!(reader.next_chunk(), Err(Error::IOError(_))));
+
+        // a data offset past the end of the chunk table
+        let mut bad_offset = blte.clone();
+        bad_offset[4..8].copy_from_slice(&0x1000_0000u32.to_be_bytes());
+        assert!(matches!(BLTEReader::from_reader(bad_offset.as_slice(), &keys), Err(Error::InvalidBlteDataOffset(0x1000_0000))));
+    }
+
+    #[test]
+    fn test_blte_reader_headerless() {
+        let blte = blte_wrap(b"headerless");
+        let keys = KeyStore::default();
+
+        let mut reader = BLTEReader::from_reader(blte.as_slice(), &keys).unwrap();
+        reader.verify_ekey(&EKey(Md5::digest(&blte).into())).unwrap();
+        assert_eq!(reader.next_chunk().unwrap().unwrap(), b"headerless");
+        assert!(reader.next_chunk().unwrap().is_none());
+
+        // the mismatch only shows up once the frame has been read
+        let mut reader = BLTEReader::from_reader(blte.as_slice(), &keys).unwrap();
+        reader.verify_ekey(&EKey([0; 16])).unwrap();
+        assert!(matches!(reader.next_chunk(), Err(Error::BlteEKeyMismatch(_))));
+    }
+
+    #[test]
+    fn test_blte_encode_roundtrip() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let espec: ESpec = "b:{1000=n,16K*2=z,*=z:1}".parse().unwrap();
+        let (ekey, blte) = encode_blte(&data, &espec).unwrap();
+
+        verify_blte(&blte, Some(&ekey)).unwrap();
+        let header = BLTEHeader::from_bytes((&blte, 0)).unwrap().1;
+        assert_eq!(header.chunks.len(), 4);
+        assert_eq!(decode_blte(&blte).unwrap(), data);
+
+        let (_, blte) = encode_blte(&data, &"b:{256K*=z}".parse().unwrap()).unwrap();
+        assert_eq!(decode_blte(&blte).unwrap(), data);
+        assert!(encode_blte(&data, &"b:{1K*4=n}".parse().unwrap()).is_err());
+
+        // only the window size the compressor actually uses can be asked for
+        let (_, blte) = encode_blte(&data, &"z:{6,15}".parse().unwrap()).unwrap();
+        assert_eq!(decode_blte(&blte).unwrap(), data);
+        for espec in ["z:{9,12}", "z:{9,mpq}", "b:{16K=n,*=z:{9,8}}"] {
+            assert!(matches!(encode_blte(&data, &espec.parse().unwrap()), Err(Error::InvalidESpec(_))));
+        }
+    }
 }
@@ -1,13 +1,26 @@
+use std::ffi::CString;
 use std::str::FromStr;
 
 use deku::DekuRead;
 
+use crate::error::Error;
+
 macro_rules! impl_key {
     ($name:ident) => {
+        // transparent, so that keys can be borrowed straight out of a buffer
         #[derive(DekuRead, Debug, PartialEq, Eq, Hash, Clone)]
+        #[repr(transparent)]
         pub struct $name(pub [u8; 16]);
 
         impl $name {
+            // Borrows each 16 bytes of `bytes` as a key, ignoring any
+            // trailing partial key
+            pub fn slice_from_bytes(bytes: &[u8]) -> &[Self] {
+                // SAFETY: the key is a transparent wrapper around [u8; 16], so
+                // it has the same size and an alignment of 1
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const Self, bytes.len() / 16) }
+            }
+
             pub fn to_string(&self) -> String {
                 let mut result = String::new();
                 let $name(hex) = self;
@@ -27,8 +40,9 @@ macro_rules! impl_key {
                 }
                 let mut key = [0; 16];
                 for i in 0..16 {
-                    let hex = &s[i*2..i*2+2];
-                    key[i] = u8::from_str_radix(hex, 16).unwrap();
+                    key[i] = s.get(i*2..i*2+2)
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| format!("invalid hex string {}", s))?;
                 }
                 Ok(Self(key))
             }
@@ -41,15 +55,100 @@ impl_key!(EKey);
 
 pub const NULL_EKEY: EKey = EKey([0; 16]);
 
+// A tag from the install, download or size manifests (e.g. "Windows" or
+// "enUS"), marking which of the manifest's entries it applies to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    pub tag_type: u16,
+    pub mask: Vec<u8>,
+}
+
+impl Tag {
+    pub fn contains(&self, entry_index: usize) -> bool {
+        self.mask.get(entry_index / 8)
+            .is_some_and(|byte| byte & (0x80 >> (entry_index % 8)) != 0)
+    }
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(ctx = "num_entries: usize")]
+pub(crate) struct RawTag {
+    name: CString,
+    #[deku(endian = "big")]
+    tag_type: u16,
+    #[deku(count = "num_entries.div_ceil(8)")]
+    mask: Vec<u8>,
+}
+
+impl From<RawTag> for Tag {
+    fn from(tag: RawTag) -> Self {
+        Tag {
+            name: tag.name.to_string_lossy().into_owned(),
+            tag_type: tag.tag_type,
+            mask: tag.mask,
+        }
+    }
+}
+
+// Finds the indices of the entries which have every one of the named tags
+pub(crate) fn get_tagged_entry_indices(tags: &[Tag], tag_names: &[&str], num_entries: usize) -> Result<Vec<usize>, Error> {
+    let selected = tag_names.iter()
+        .map(|&name| tags.iter().find(|tag| tag.name == name).ok_or_else(|| Error::UnknownTag(name.to_string())))
+        .collect::<Result<Vec<&Tag>, Error>>()?;
+    Ok((0..num_entries)
+        .filter(|&i| selected.iter().all(|tag| tag.contains(i)))
+        .collect())
+}
+
+// Totals the given entry sizes for each tag
+pub(crate) fn get_tag_sizes<F: Fn(usize) -> u64>(tags: &[Tag], num_entries: usize, entry_size: F) -> Vec<(String, u64)> {
+    tags.iter()
+        .map(|tag| {
+            let size = (0..num_entries)
+                .filter(|&i| tag.contains(i))
+                .map(&entry_size)
+                .sum();
+            (tag.name.clone(), size)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_tags() {
+        let tags = vec![
+            Tag { name: "Windows".into(), tag_type: 1, mask: vec![0b1010_0000] },
+            Tag { name: "enUS".into(), tag_type: 3, mask: vec![0b0110_0000] },
+        ];
+        assert!(tags[0].contains(0));
+        assert!(!tags[0].contains(1));
+        assert!(!tags[0].contains(9));
+        assert_eq!(get_tagged_entry_indices(&tags, &["Windows", "enUS"], 3).unwrap(), vec![2]);
+        assert_eq!(get_tagged_entry_indices(&tags, &[], 3).unwrap(), vec![0, 1, 2]);
+        assert!(get_tagged_entry_indices(&tags, &["OSX"], 3).is_err());
+        assert_eq!(get_tag_sizes(&tags, 3, |i| 10 << i), vec![("Windows".into(), 50), ("enUS".into(), 60)]);
+    }
+
     #[test]
     fn test_ekey_conversion() {
         let s = "0017a402f556fbece46c38dc431a2c9b";
         let key: EKey = EKey([0x00, 0x17, 0xa4, 0x02, 0xf5, 0x56, 0xfb, 0xec, 0xe4, 0x6c, 0x38, 0xdc, 0x43, 0x1a, 0x2c, 0x9b]);
         assert_eq!(EKey::from_str(s), Ok(key.clone()));
         assert_eq!(key.to_string(), s.to_string());
+        assert!(EKey::from_str("0017a402f556fbece46c38dc431a2c9g").is_err());
+        assert!(EKey::from_str("0017a402f556fbece46c38dc431a2\u{e9}b").is_err());
+    }
+
+    #[test]
+    fn test_key_slice_from_bytes() {
+        let mut bytes = [0x11; 16].to_vec();
+        bytes.extend([0x22; 16]);
+        bytes.push(0x33);
+        assert_eq!(EKey::slice_from_bytes(&bytes), [EKey([0x11; 16]), EKey([0x22; 16])].as_slice());
+        assert!(CKey::slice_from_bytes(&bytes[..15]).is_empty());
     }
 }
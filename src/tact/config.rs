@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::tact::common::{CKey, EKey};
+use crate::tact::espec::ESpec;
+
+// Parses a `key = value value ...` config file, as used for build, CDN and
+// patch configs. Blank lines and `#` comments are skipped.
+pub fn parse_config(data: &[u8]) -> Result<HashMap<String, Vec<String>>, Error> {
+    Ok(parse_config_lines(data)?.into_iter().collect())
+}
+
+// Like parse_config, but keeping every line in order, since some keys (like
+// patch configs' `patch-entry`) appear on more than one
+fn parse_config_lines(data: &[u8]) -> Result<Vec<(String, Vec<String>)>, Error> {
+    let data = std::str::from_utf8(data).map_err(|e| {
+        let line = data[..e.valid_up_to()].iter().filter(|&&b| b == b'\n').count() + 1;
+        Error::InvalidConfigLine(line)
+    })?;
+
+    let mut result = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+
+        let (k, v) = line.split_once('=').ok_or(Error::InvalidConfigLine(i + 1))?;
+        result.push((k.trim().to_string(), v.split_whitespace().map(|s| s.to_string()).collect()));
+    }
+    Ok(result)
+}
+
+// A file named by a build config as `<name> = <ckey> [ekey]`, along with the
+// sizes from its `<name>-size = <decoded size> [encoded size]` entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigFile {
+    pub ckey: CKey,
+    pub ekey: Option<EKey>,
+    pub decoded_size: Option<u64>,
+    pub encoded_size: Option<u64>,
+}
+
+// Typed accessors over a parsed config, which turn missing or malformed
+// values into errors naming the offending key
+struct ConfigEntries<'a>(&'a HashMap<String, Vec<String>>);
+
+impl ConfigEntries<'_> {
+    fn get_values(&self, key: &str) -> Option<&[String]> {
+        self.0.get(key).map(|values| values.as_slice())
+    }
+
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.get_values(key).map(|values| values.join(" "))
+    }
+
+    // The first value of a key which names another config file or archive
+    fn get_key(&self, key: &str) -> Result<Option<String>, Error> {
+        match self.get_values(key) {
+            None => Ok(None),
+            Some([value, ..]) => Ok(Some(value.clone())),
+            Some([]) => Err(Error::InvalidConfigValue(key.to_string())),
+        }
+    }
+
+    fn get_keys(&self, key: &str) -> Vec<String> {
+        self.get_values(key).map(|values| values.to_vec()).unwrap_or_default()
+    }
+
+    fn parse_value<T: FromStr>(&self, key: &str, value: &str) -> Result<T, Error> {
+        value.parse().map_err(|_| Error::InvalidConfigValue(key.to_string()))
+    }
+
+    fn get_value<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+        self.get_key(key)?
+            .map(|value| self.parse_value(key, &value))
+            .transpose()
+    }
+
+    fn get_values_parsed<T: FromStr>(&self, key: &str) -> Result<Vec<T>, Error> {
+        self.get_keys(key).iter()
+            .map(|value| self.parse_value(key, value))
+            .collect()
+    }
+
+    fn get_file(&self, key: &str) -> Result<Option<ConfigFile>, Error> {
+        let Some(values) = self.get_values(key) else {
+            return Ok(None);
+        };
+        let (ckey, ekey) = match values {
+            [ckey] => (self.parse_value(key, ckey)?, None),
+            [ckey, ekey, ..] => (self.parse_value(key, ckey)?, Some(self.parse_value(key, ekey)?)),
+            [] => return Err(Error::InvalidConfigValue(key.to_string())),
+        };
+        let size_key = format!("{}-size", key);
+        let sizes: Vec<u64> = self.get_values_parsed(&size_key)?;
+        Ok(Some(ConfigFile {
+            ckey,
+            ekey,
+            decoded_size: sizes.first().copied(),
+            encoded_size: sizes.get(1).copied(),
+        }))
+    }
+
+    fn require<T>(key: &str, value: Option<T>) -> Result<T, Error> {
+        value.ok_or_else(|| Error::MissingConfigKey(key.to_string()))
+    }
+}
+
+// The build config, naming the files which make up a build. See
+// https://wowdev.wiki/TACT#Build_Config
+#[derive(Clone, Debug)]
+pub struct BuildConfig {
+    pub root: CKey,
+    pub encoding: ConfigFile,
+    // the encoding file always needs an EKey, since it's what maps CKeys to
+    // EKeys
+    pub encoding_ekey: EKey,
+    pub install: Option<ConfigFile>,
+    pub download: Option<ConfigFile>,
+    pub size: Option<ConfigFile>,
+    pub patch: Option<EKey>,
+    pub patch_size: Option<u64>,
+    pub patch_config: Option<EKey>,
+    pub build_name: Option<String>,
+    pub build_uid: Option<String>,
+    pub build_product: Option<String>,
+    pub vfs_root: Option<ConfigFile>,
+    // vfs-1, vfs-2, ... in order
+    pub vfs: Vec<ConfigFile>,
+    // every entry, including ones not covered above
+    pub entries: HashMap<String, Vec<String>>,
+}
+
+impl BuildConfig {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let entries = parse_config(data)?;
+        let config = ConfigEntries(&entries);
+
+        let encoding = ConfigEntries::require("encoding", config.get_file("encoding")?)?;
+        let encoding_ekey = encoding.ekey.clone().ok_or_else(|| Error::InvalidConfigValue("encoding".to_string()))?;
+
+        let mut vfs = Vec::new();
+        while let Some(file) = config.get_file(&format!("vfs-{}", vfs.len() + 1))? {
+            vfs.push(file);
+        }
+
+        Ok(BuildConfig {
+            root: ConfigEntries::require("root", config.get_value("root")?)?,
+            encoding,
+            encoding_ekey,
+            install: config.get_file("install")?,
+            download: config.get_file("download")?,
+            size: config.get_file("size")?,
+            patch: config.get_value("patch")?,
+            patch_size: config.get_value("patch-size")?,
+            patch_config: config.get_value("patch-config")?,
+            build_name: config.get_string("build-name"),
+            build_uid: config.get_string("build-uid"),
+            build_product: config.get_string("build-product"),
+            vfs_root: config.get_file("vfs-root")?,
+            vfs,
+            entries,
+        })
+    }
+}
+
+// The CDN config, listing the archives and indices a build's data is spread
+// over. See https://wowdev.wiki/TACT#CDN_Config
+#[derive(Clone, Debug)]
+pub struct CDNConfig {
+    pub archives: Vec<EKey>,
+    // the size of each archive's .index, in the same order
+    pub archives_index_size: Vec<u64>,
+    pub archive_group: Option<EKey>,
+    pub patch_archives: Vec<EKey>,
+    pub patch_archives_index_size: Vec<u64>,
+    pub patch_archive_group: Option<EKey>,
+    pub file_index: Option<EKey>,
+    pub file_index_size: Option<u64>,
+    pub patch_file_index: Option<EKey>,
+    pub patch_file_index_size: Option<u64>,
+    // the build configs which use this CDN config
+    pub builds: Vec<String>,
+    // every entry, including ones not covered above
+    pub entries: HashMap<String, Vec<String>>,
+}
+
+impl CDNConfig {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let entries = parse_config(data)?;
+        let config = ConfigEntries(&entries);
+        ConfigEntries::require("archives", config.get_values("archives"))?;
+        Ok(CDNConfig {
+            archives: config.get_values_parsed("archives")?,
+            archives_index_size: config.get_values_parsed("archives-index-size")?,
+            archive_group: config.get_value("archive-group")?,
+            patch_archives: config.get_values_parsed("patch-archives")?,
+            patch_archives_index_size: config.get_values_parsed("patch-archives-index-size")?,
+            patch_archive_group: config.get_value("patch-archive-group")?,
+            file_index: config.get_value("file-index")?,
+            file_index_size: config.get_value("file-index-size")?,
+            patch_file_index: config.get_value("patch-file-index")?,
+            patch_file_index_size: config.get_value("patch-file-index-size")?,
+            builds: config.get_keys("builds"),
+            entries,
+        })
+    }
+}
+
+// The patch config, describing how to patch a build's manifests (encoding,
+// install, etc.) from older builds' versions of them. See
+// https://wowdev.wiki/TACT#Patch_Config
+#[derive(Clone, Debug)]
+pub struct PatchConfig {
+    // the patch manifest
+    pub patch: EKey,
+    pub patch_size: Option<u64>,
+    // one per `patch-entry` line
+    pub patch_entries: Vec<PatchEntry>,
+    // every entry, including ones not covered above. Only the last
+    // `patch-entry` line is kept here.
+    pub entries: HashMap<String, Vec<String>>,
+}
+
+// A file of the build, as `<name> <ckey> <size> <ekey> <encoded size> <espec>`,
+// followed by the patches to it from older builds
+#[derive(Clone, Debug)]
+pub struct PatchEntry {
+    pub name: String,
+    pub ckey: CKey,
+    pub decoded_size: u64,
+    pub ekey: EKey,
+    pub encoded_size: u64,
+    pub espec: ESpec,
+    pub patches: Vec<PatchEntrySource>,
+}
+
+// An older version of a patch entry's file, as `<old ekey> <old size> <patch
+// ekey> <patch size>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatchEntrySource {
+    pub old_ekey: EKey,
+    pub old_decoded_size: u64,
+    pub patch_ekey: EKey,
+    pub patch_size: u64,
+}
+
+impl PatchEntry {
+    fn parse(config: &ConfigEntries, values: &[String]) -> Result<Self, Error> {
+        const KEY: &str = "patch-entry";
+        let ([name, ckey, decoded_size, ekey, encoded_size, espec], patches) = match values.split_first_chunk() {
+            Some((entry, patches)) if patches.len() % 4 == 0 => (entry, patches),
+            _ => return Err(Error::InvalidConfigValue(KEY.to_string())),
+        };
+        Ok(PatchEntry {
+            name: name.clone(),
+            ckey: config.parse_value(KEY, ckey)?,
+            decoded_size: config.parse_value(KEY, decoded_size)?,
+            ekey: config.parse_value(KEY, ekey)?,
+            encoded_size: config.parse_value(KEY, encoded_size)?,
+            espec: config.parse_value(KEY, espec)?,
+            patches: patches.chunks(4)
+                .map(|patch| Ok(PatchEntrySource {
+                    old_ekey: config.parse_value(KEY, &patch[0])?,
+                    old_decoded_size: config.parse_value(KEY, &patch[1])?,
+                    patch_ekey: config.parse_value(KEY, &patch[2])?,
+                    patch_size: config.parse_value(KEY, &patch[3])?,
+                }))
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+impl PatchConfig {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let lines = parse_config_lines(data)?;
+        let entries: HashMap<String, Vec<String>> = lines.iter().cloned().collect();
+        let config = ConfigEntries(&entries);
+        let patch_entries = lines.iter()
+            .filter(|(key, _)| key == "patch-entry")
+            .map(|(_, values)| PatchEntry::parse(&config, values))
+            .collect::<Result<_, Error>>()?;
+        Ok(PatchConfig {
+            patch: ConfigEntries::require("patch", config.get_value("patch")?)?,
+            patch_size: config.get_value("patch-size")?,
+            patch_entries,
+            entries,
+        })
+    }
+
+    pub fn get_patch_entry(&self, name: &str) -> Option<&PatchEntry> {
+        self.patch_entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CKEY: &str = "0017a402f556fbece46c38dc431a2c9b";
+    const EKEY: &str = "ff17a402f556fbece46c38dc431a2c9b";
+
+    #[test]
+    fn test_build_config() {
+        let data = format!("# Build Configuration
+
+root = {CKEY}
+install = {CKEY} {EKEY}
+install-size = 1000 500
+download = {CKEY} {EKEY}
+encoding = {CKEY} {EKEY}
+encoding-size = 2000 1000
+patch-config = {EKEY}
+build-name = WOW-12345patch1.2.3_Retail
+vfs-root = {CKEY} {EKEY}
+vfs-1 = {CKEY} {EKEY}
+vfs-1-size = 10 20
+vfs-2 = {CKEY} {EKEY}
+");
+        let config = BuildConfig::parse(data.as_bytes()).unwrap();
+        let ckey: CKey = CKEY.parse().unwrap();
+        let ekey: EKey = EKEY.parse().unwrap();
+        assert_eq!(config.root, ckey);
+        assert_eq!(config.encoding_ekey, ekey);
+        assert_eq!(config.encoding.encoded_size, Some(1000));
+        assert_eq!(config.install.unwrap().decoded_size, Some(1000));
+        assert_eq!(config.download.unwrap().decoded_size, None);
+        assert!(config.size.is_none());
+        assert!(config.patch.is_none());
+        assert_eq!(config.patch_config, Some(ekey.clone()));
+        assert_eq!(config.build_name.as_deref(), Some("WOW-12345patch1.2.3_Retail"));
+        assert_eq!(config.vfs.len(), 2);
+        assert_eq!(config.vfs[0].encoded_size, Some(20));
+
+        let missing_root = data.replace(&format!("root = {CKEY}\n"), "");
+        assert!(matches!(BuildConfig::parse(missing_root.as_bytes()), Err(Error::MissingConfigKey(key)) if key == "root"));
+        let bad_encoding = data.replace(&format!("encoding = {CKEY} {EKEY}"), &format!("encoding = {CKEY} nothex"));
+        assert!(matches!(BuildConfig::parse(bad_encoding.as_bytes()), Err(Error::InvalidConfigValue(key)) if key == "encoding"));
+        let no_encoding_ekey = data.replace(&format!("encoding = {CKEY} {EKEY}"), &format!("encoding = {CKEY}"));
+        assert!(matches!(BuildConfig::parse(no_encoding_ekey.as_bytes()), Err(Error::InvalidConfigValue(key)) if key == "encoding"));
+        let bad_patch_config = data.replace(&format!("patch-config = {EKEY}"), "patch-config = aa");
+        assert!(matches!(BuildConfig::parse(bad_patch_config.as_bytes()), Err(Error::InvalidConfigValue(key)) if key == "patch-config"));
+        let bad_size = data.replace("install-size = 1000 500", "install-size = big");
+        assert!(matches!(BuildConfig::parse(bad_size.as_bytes()), Err(Error::InvalidConfigValue(key)) if key == "install-size"));
+    }
+
+    #[test]
+    fn test_patch_config() {
+        let data = format!("# Patch Configuration
+patch = {EKEY}
+patch-entry = encoding {CKEY} 2000 {EKEY} 1000 b:{{16K*=z}} {EKEY} 1900 {CKEY} 50 {CKEY} 1800 {EKEY} 60
+patch-entry = install {CKEY} 300 {EKEY} 200 z
+patch-size = 5000
+");
+        let config = PatchConfig::parse(data.as_bytes()).unwrap();
+        let ckey: CKey = CKEY.parse().unwrap();
+        let ekey: EKey = EKEY.parse().unwrap();
+        assert_eq!(config.patch, ekey);
+        assert_eq!(config.patch_size, Some(5000));
+        assert_eq!(config.patch_entries.len(), 2);
+
+        let encoding = config.get_patch_entry("encoding").unwrap();
+        assert_eq!(encoding.ckey, ckey);
+        assert_eq!(encoding.decoded_size, 2000);
+        assert_eq!(encoding.encoded_size, 1000);
+        assert_eq!(encoding.espec.to_string(), "b:{16K*=z}");
+        assert_eq!(encoding.patches, vec![
+            PatchEntrySource { old_ekey: ekey.clone(), old_decoded_size: 1900, patch_ekey: EKey(ckey.0), patch_size: 50 },
+            PatchEntrySource { old_ekey: EKey(ckey.0), old_decoded_size: 1800, patch_ekey: ekey.clone(), patch_size: 60 },
+        ]);
+        assert!(config.get_patch_entry("install").unwrap().patches.is_empty());
+        assert!(config.get_patch_entry("download").is_none());
+
+        let missing_patch = data.replace(&format!("patch = {EKEY}\n"), "");
+        assert!(matches!(PatchConfig::parse(missing_patch.as_bytes()), Err(Error::MissingConfigKey(key)) if key == "patch"));
+        let short_entry = data.replace(" z\n", " z {EKEY}\n");
+        assert!(matches!(PatchConfig::parse(short_entry.as_bytes()), Err(Error::InvalidConfigValue(key)) if key == "patch-entry"));
+    }
+
+    #[test]
+    fn test_cdn_config() {
+        let data = format!("# CDN Configuration
+archives = {CKEY} {EKEY}
+archives-index-size = 1 2
+archive-group = {EKEY}
+patch-archives = {CKEY}
+file-index = {EKEY}
+file-index-size = 4
+builds = 11 22
+");
+        let config = CDNConfig::parse(data.as_bytes()).unwrap();
+        let ckey_as_ekey: EKey = CKEY.parse().unwrap();
+        let ekey: EKey = EKEY.parse().unwrap();
+        assert_eq!(config.archives, vec![ckey_as_ekey.clone(), ekey.clone()]);
+        assert_eq!(config.archives_index_size, vec![1, 2]);
+        assert_eq!(config.archive_group, Some(ekey.clone()));
+        assert_eq!(config.patch_archives, vec![ckey_as_ekey]);
+        assert!(config.patch_archive_group.is_none());
+        assert_eq!(config.file_index, Some(ekey));
+        assert_eq!(config.file_index_size, Some(4));
+        assert_eq!(config.builds, vec!["11", "22"]);
+
+        // keys end up in CDN URLs, so they have to be real hex keys
+        let bad_configs = [
+            ("archives", "archives = aa bb cc\n".to_string()),
+            ("archive-group", format!("archives = {EKEY}\narchive-group = dd\n")),
+            ("patch-file-index", format!("archives = {EKEY}\npatch-file-index = ../{CKEY}\n")),
+        ];
+        for (key, bad_config) in bad_configs {
+            assert!(matches!(CDNConfig::parse(bad_config.as_bytes()), Err(Error::InvalidConfigValue(k)) if k == key));
+        }
+
+        assert!(matches!(CDNConfig::parse(format!("archive-group = {EKEY}\n").as_bytes()), Err(Error::MissingConfigKey(key)) if key == "archives"));
+        assert!(matches!(CDNConfig::parse(b"archives = aa\nnot a config line\n"), Err(Error::InvalidConfigLine(2))));
+        assert!(matches!(CDNConfig::parse(b"archives = aa\n\xff\n"), Err(Error::InvalidConfigLine(2))));
+    }
+}
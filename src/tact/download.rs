@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use deku::{DekuRead, DekuContainerRead};
+
+use crate::error::Error;
+use crate::tact::blte::decode_blte;
+use crate::tact::common::{get_tag_sizes, get_tagged_entry_indices, EKey, RawTag, Tag};
+
+// The download manifest, listing every file a client downloads along with its
+// priority, where 0 is needed before the game can start. See
+// https://wowdev.wiki/TACT#Download_manifest
+#[derive(Clone, Debug)]
+pub struct DownloadManifest {
+    pub version: u8,
+    pub tags: Vec<Tag>,
+    pub entries: Vec<DownloadEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadEntry {
+    pub ekey: EKey,
+    pub size: u64,
+    // wider than the raw priority, since subtracting the base priority can
+    // take it out of i8's range
+    pub priority: i16,
+    pub checksum: Option<u32>,
+    pub flags: Vec<u8>,
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(ctx = "has_checksum: bool, num_flag_bytes: usize")]
+struct RawDownloadEntry {
+    ekey: EKey,
+    #[deku(endian = "big", bytes = 5)]
+    size: u64,
+    priority: i8,
+    #[deku(cond = "has_checksum", endian = "big")]
+    checksum: Option<u32>,
+    #[deku(count = "num_flag_bytes")]
+    flags: Vec<u8>,
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(magic = b"DL")]
+struct DownloadManifestData {
+    #[deku(assert = "(1..=3).contains(version)")]
+    version: u8,
+    #[deku(assert_eq = "16")]
+    _ekey_size: u8,
+    _has_checksum: u8,
+    #[deku(endian = "big")]
+    _num_entries: u32,
+    #[deku(endian = "big")]
+    _num_tags: u16,
+    #[deku(cond = "*version >= 2", default = "0")]
+    _num_flag_bytes: u8,
+    #[deku(cond = "*version >= 3", default = "0")]
+    base_priority: i8,
+    #[deku(cond = "*version >= 3", default = "[0; 3]")]
+    _unknown: [u8; 3],
+    #[deku(count = "_num_entries", ctx = "*_has_checksum != 0, *_num_flag_bytes as usize")]
+    entries: Vec<RawDownloadEntry>,
+    #[deku(count = "_num_tags", ctx = "*_num_entries as usize")]
+    tags: Vec<RawTag>,
+}
+
+impl DownloadManifest {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let decode = decode_blte(data)?;
+        let (_, manifest) = DownloadManifestData::from_bytes((&decode, 0))?;
+        let base_priority = manifest.base_priority;
+        Ok(DownloadManifest {
+            version: manifest.version,
+            tags: manifest.tags.into_iter().map(Tag::from).collect(),
+            entries: manifest.entries.into_iter()
+                .map(|entry| DownloadEntry {
+                    ekey: entry.ekey,
+                    size: entry.size,
+                    // version 3 priorities are relative to a base priority
+                    priority: entry.priority as i16 - base_priority as i16,
+                    checksum: entry.checksum,
+                    flags: entry.flags,
+                })
+                .collect(),
+        })
+    }
+
+    // The EKeys of every file at or above the given priority (i.e. with a
+    // priority number no greater than it)
+    pub fn get_ekeys_with_priority(&self, max_priority: i8) -> HashSet<EKey> {
+        self.entries.iter()
+            .filter(|entry| entry.priority <= max_priority as i16)
+            .map(|entry| entry.ekey.clone())
+            .collect()
+    }
+
+    // Finds the entries which have every one of the named tags
+    pub fn get_entries_with_tags(&self, tag_names: &[&str]) -> Result<Vec<&DownloadEntry>, Error> {
+        let indices = get_tagged_entry_indices(&self.tags, tag_names, self.entries.len())?;
+        Ok(indices.into_iter().map(|i| &self.entries[i]).collect())
+    }
+
+    // The total download size of each tag's files
+    pub fn get_tag_sizes(&self) -> Vec<(String, u64)> {
+        get_tag_sizes(&self.tags, self.entries.len(), |i| self.entries[i].size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tact::blte::blte_wrap;
+
+    // Builds a version 3 manifest, with checksums and a flag byte, out of
+    // (size, raw priority) entries and a single tag
+    fn make_download_manifest(entries: &[(u64, i8)], base_priority: i8, tag_mask: u8) -> Vec<u8> {
+        let mut data = b"DL".to_vec();
+        data.extend([3, 16, 1]);
+        data.extend((entries.len() as u32).to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.push(1);
+        data.push(base_priority as u8);
+        data.extend([0; 3]);
+        for (i, (size, priority)) in entries.iter().enumerate() {
+            data.extend([i as u8; 16]);
+            data.extend(&size.to_be_bytes()[3..]);
+            data.push(*priority as u8);
+            data.extend(0xdeadbeef_u32.to_be_bytes());
+            data.push(0x80);
+        }
+        data.extend(b"enUS\0");
+        data.extend(3u16.to_be_bytes());
+        data.push(tag_mask);
+        blte_wrap(&data)
+    }
+
+    #[test]
+    fn test_download_manifest() {
+        let data = make_download_manifest(&[(0x1_0000_0000, 1), (20, 2), (30, 3)], 1, 0b0110_0000);
+        let manifest = DownloadManifest::parse(&data).unwrap();
+        assert_eq!(manifest.entries.len(), 3);
+        assert_eq!(manifest.entries[0].size, 0x1_0000_0000);
+        assert_eq!(manifest.entries[0].priority, 0);
+        assert_eq!(manifest.entries[2].checksum, Some(0xdeadbeef));
+        assert_eq!(manifest.entries[2].flags, vec![0x80]);
+
+        let priority_0 = manifest.get_ekeys_with_priority(0);
+        assert_eq!(priority_0.len(), 1);
+        assert!(priority_0.contains(&EKey([0; 16])));
+        assert_eq!(manifest.get_entries_with_tags(&["enUS"]).unwrap().len(), 2);
+        assert_eq!(manifest.get_tag_sizes(), vec![("enUS".into(), 50)]);
+
+        // relative priorities past i8's range don't wrap around
+        let data = make_download_manifest(&[(10, 127), (20, -128)], -2, 0);
+        let manifest = DownloadManifest::parse(&data).unwrap();
+        assert_eq!(manifest.entries[0].priority, 129);
+        assert_eq!(manifest.entries[1].priority, -126);
+        let data = make_download_manifest(&[(10, 127), (20, -128)], 2, 0);
+        let manifest = DownloadManifest::parse(&data).unwrap();
+        assert_eq!(manifest.entries[1].priority, -130);
+        assert_eq!(manifest.get_ekeys_with_priority(0), HashSet::from([EKey([1; 16])]));
+    }
+}
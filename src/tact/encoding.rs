@@ -1,21 +1,75 @@
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 use deku::{DekuRead, DekuContainerRead};
+use md5::{Digest, Md5};
 
 use crate::tact::blte::decode_blte;
 use crate::error::Error;
-use crate::tact::common::{CKey, EKey};
+use crate::tact::common::{CKey, EKey, NULL_EKEY};
+use crate::tact::espec::ESpec;
 
 #[derive(Clone, Debug)]
 pub struct EncodingFile {
-    pub ckey_to_ekey: HashMap<CKey, EKey>,
+    backend: EncodingBackend,
+    pub especs: Vec<ESpec>,
+    pub ckey_page_first_keys: Vec<CKey>,
+    pub ekey_page_first_keys: Vec<EKey>,
+    // only built on first use, since most callers never need it
+    ekey_to_ckey: OnceLock<HashMap<EKey, CKey>>,
+}
+
+// The hashed backend parses every entry up front for fast lookups, while the
+// paged backend only keeps the verified page bytes around, and on each lookup
+// searches the one page the key could be in. That uses a fraction of the
+// memory.
+#[derive(Clone, Debug)]
+enum EncodingBackend {
+    Hashed {
+        ckey_to_entry: HashMap<CKey, CKeyEntry>,
+        ekey_to_spec: HashMap<EKey, EKeySpec>,
+    },
+    Paged {
+        // each table's pages, back to back
+        ckey_pages: Vec<u8>,
+        page_size_ckey: usize,
+        ekey_pages: Vec<u8>,
+        page_size_ekey: usize,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct CKeyEntry {
+    pub ekeys: Vec<EKey>,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct EKeySpec {
+    pub espec_index: u32,
+    pub encoded_size: u64,
+}
+
+#[derive(DekuRead, Debug)]
+struct EncodingFilePageIndexEntry {
+    pub first_key: [u8; 16],
+    pub checksum: [u8; 16],
+}
+
+#[derive(DekuRead, Debug)]
+struct EncodingFileEKeyEntry {
+    pub ekey: EKey,
+    #[deku(endian = "big")]
+    pub espec_index: u32,
+    #[deku(endian = "big", bytes = 5)]
+    pub encoded_size: u64,
 }
 
 #[derive(DekuRead, Debug)]
 struct EncodingFilePage {
     pub ekey_count: u8,
-    #[deku(pad_bytes_before = "1", endian = "big")]
-    pub _size: u32, // Technically this is a 40-bit size value. We chop off the first byte here... hope it doesn't matter!
+    #[deku(endian = "big", bytes = 5)]
+    pub size: u64,
     pub ckey: CKey,
     #[deku(count = "ekey_count")]
     pub ekeys: Vec<EKey>,
@@ -25,54 +79,277 @@ struct EncodingFilePage {
 #[deku(magic = b"EN", endian = "big")]
 struct EncodingFileHeader {
     pub _version: u8,
-    pub hash_size_ckey: u8,
+    #[deku(assert_eq = "16")]
+    pub _hash_size_ckey: u8,
+    #[deku(assert_eq = "16")]
     pub _hash_size_ekey: u8,
     pub page_size_ckey: u16,
-    pub _page_size_ekey: u16,
+    pub page_size_ekey: u16,
     pub page_count_ckey: u32,
-    pub _page_count_ekey: u32,
+    pub page_count_ekey: u32,
     #[deku(assert_eq = "0")]
     _pad1: u8,
     pub espec_page_size: u32,
 }
 
+fn parse_espec_table(data: &[u8]) -> Result<Vec<ESpec>, Error> {
+    data.split(|&b| b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let s = std::str::from_utf8(s).map_err(|_| Error::InvalidESpec(String::from_utf8_lossy(s).into_owned()))?;
+            s.parse()
+        })
+        .collect()
+}
+
+struct VerifiedPages<'a> {
+    first_keys: Vec<[u8; 16]>,
+    pages: Vec<&'a [u8]>,
+    // all of the pages, back to back
+    bytes: &'a [u8],
+    end: usize,
+}
+
+// Reads a page index table starting at `offs`, followed by its pages, and
+// checks each page against its MD5 and that the pages' first keys are in
+// order.
+fn read_verified_pages<'a>(data: &'a [u8], offs: usize, page_count: usize, page_size: usize, table_name: &'static str) -> Result<VerifiedPages<'a>, Error> {
+    let table_size = page_count * 0x20;
+    let pages_start = offs + table_size;
+    let pages_end = pages_start + page_count * page_size;
+    if pages_end > data.len() {
+        return Err(Error::TruncatedEncodingFile);
+    }
+
+    let mut first_keys: Vec<[u8; 16]> = Vec::with_capacity(page_count);
+    let mut pages = Vec::with_capacity(page_count);
+    let mut table_rest = &data[offs..pages_start];
+    for i in 0..page_count {
+        let ((new_table_rest, _), index_entry) = EncodingFilePageIndexEntry::from_bytes((table_rest, 0))?;
+        table_rest = new_table_rest;
+
+        let page = &data[pages_start + page_size * i .. pages_start + page_size * (i + 1)];
+        let checksum: [u8; 16] = Md5::digest(page).into();
+        if checksum != index_entry.checksum {
+            return Err(Error::EncodingPageChecksumMismatch(table_name, i));
+        }
+        if first_keys.last().is_some_and(|prev| *prev >= index_entry.first_key) {
+            return Err(Error::InvalidEncodingPageIndex(table_name, i));
+        }
+        first_keys.push(index_entry.first_key);
+        pages.push(page);
+    }
+    Ok(VerifiedPages { first_keys, pages, bytes: &data[pages_start..pages_end], end: pages_end })
+}
+
+fn iter_ckey_page(page: &[u8]) -> impl Iterator<Item = EncodingFilePage> + '_ {
+    let mut page_rest = page;
+    std::iter::from_fn(move || {
+        let ((new_page_rest, _), entry) = EncodingFilePage::from_bytes((page_rest, 0)).ok()?;
+        page_rest = new_page_rest;
+        if entry.ekey_count == 0 {
+            return None;
+        }
+        Some(entry)
+    })
+}
+
+// Finds the CKey's entry in a page, borrowing its EKeys from the page
+fn find_in_ckey_page<'a>(page: &'a [u8], ckey: &CKey) -> Option<(&'a [EKey], u64)> {
+    let mut page_rest = page;
+    loop {
+        let ((new_page_rest, _), entry) = EncodingFilePage::from_bytes((page_rest, 0)).ok()?;
+        if entry.ekey_count == 0 {
+            return None;
+        }
+        if entry.ckey == *ckey {
+            let entry_bytes = &page_rest[..page_rest.len() - new_page_rest.len()];
+            let ekeys = EKey::slice_from_bytes(&entry_bytes[entry_bytes.len() - entry.ekeys.len() * 16..]);
+            return Some((ekeys, entry.size));
+        }
+        page_rest = new_page_rest;
+    }
+}
+
+fn iter_ekey_page(page: &[u8]) -> impl Iterator<Item = EncodingFileEKeyEntry> + '_ {
+    let mut page_rest = page;
+    std::iter::from_fn(move || {
+        let ((new_page_rest, _), entry) = EncodingFileEKeyEntry::from_bytes((page_rest, 0)).ok()?;
+        page_rest = new_page_rest;
+        // pages are padded out with zeroes, or with an espec index of -1
+        if entry.ekey == NULL_EKEY || entry.espec_index == u32::MAX {
+            return None;
+        }
+        Some(entry)
+    })
+}
+
 impl EncodingFile {
     pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        Self::parse_with_backend(data, false)
+    }
+
+    // Parses the encoding file without building the entry maps, looking
+    // entries up in their page instead. Lookups are slower, but this takes far
+    // less memory for large products. get_ckey_for_ekey still builds its
+    // reverse map on first use.
+    pub fn parse_paged(data: &[u8]) -> Result<Self, Error> {
+        Self::parse_with_backend(data, true)
+    }
+
+    fn parse_with_backend(data: &[u8], paged: bool) -> Result<Self, Error> {
         let decode = decode_blte(data)?;
         let ((rest, _), header) = EncodingFileHeader::from_bytes((&decode, 0))?;
 
-        let mut ckey_to_ekey = HashMap::new();
-        let page_start_ckey = header.espec_page_size + header.page_count_ckey * ((header.hash_size_ckey as u32) + 0x10);
-        let page_size_ckey = (header.page_size_ckey as u32) * 1024;
-
-        for i in 0..header.page_count_ckey {
-            let offs = (page_start_ckey + page_size_ckey * i) as usize;
-            let page_end = offs + (page_size_ckey as usize);
+        let espec_page_size = header.espec_page_size as usize;
+        let especs = parse_espec_table(rest.get(..espec_page_size).ok_or(Error::TruncatedEncodingFile)?)?;
 
-            let mut page_rest = &rest[offs .. page_end];
-            loop {
+        let page_size_ckey = (header.page_size_ckey as usize) * 1024;
+        let ckey_pages = read_verified_pages(rest, espec_page_size, header.page_count_ckey as usize, page_size_ckey, "CKey")?;
+        for (i, (first_key, page)) in ckey_pages.first_keys.iter().zip(&ckey_pages.pages).enumerate() {
+            if iter_ckey_page(page).next().is_some_and(|entry| entry.ckey.0 != *first_key) {
+                return Err(Error::InvalidEncodingPageIndex("CKey", i));
+            }
+        }
 
-                let Ok(((new_page_rest, _), page)) = EncodingFilePage::from_bytes((page_rest, 0)) else {
-                    break;
-                };
+        let page_size_ekey = (header.page_size_ekey as usize) * 1024;
+        let ekey_pages = read_verified_pages(rest, ckey_pages.end, header.page_count_ekey as usize, page_size_ekey, "EKey")?;
+        for (i, (first_key, page)) in ekey_pages.first_keys.iter().zip(&ekey_pages.pages).enumerate() {
+            if iter_ekey_page(page).next().is_some_and(|entry| entry.ekey.0 != *first_key) {
+                return Err(Error::InvalidEncodingPageIndex("EKey", i));
+            }
+        }
 
-                page_rest = new_page_rest;
+        let ckey_page_first_keys = ckey_pages.first_keys.into_iter().map(CKey).collect();
+        let ekey_page_first_keys = ekey_pages.first_keys.into_iter().map(EKey).collect();
 
-                if page.ekey_count == 0 {
-                    break;
+        let backend = if paged {
+            EncodingBackend::Paged {
+                ckey_pages: ckey_pages.bytes.to_vec(),
+                page_size_ckey,
+                ekey_pages: ekey_pages.bytes.to_vec(),
+                page_size_ekey,
+            }
+        } else {
+            let mut ckey_to_entry = HashMap::new();
+            for page in &ckey_pages.pages {
+                for entry in iter_ckey_page(page) {
+                    ckey_to_entry.insert(entry.ckey, CKeyEntry {
+                        ekeys: entry.ekeys,
+                        size: entry.size,
+                    });
                 }
-
-                ckey_to_ekey.insert(page.ckey, page.ekeys[0].clone());
             }
-        }
+            let mut ekey_to_spec = HashMap::new();
+            for page in &ekey_pages.pages {
+                for entry in iter_ekey_page(page) {
+                    ekey_to_spec.insert(entry.ekey, EKeySpec {
+                        espec_index: entry.espec_index,
+                        encoded_size: entry.encoded_size,
+                    });
+                }
+            }
+            EncodingBackend::Hashed { ckey_to_entry, ekey_to_spec }
+        };
 
         Ok(EncodingFile {
-            ckey_to_ekey,
+            backend,
+            especs,
+            ckey_page_first_keys,
+            ekey_page_first_keys,
+            ekey_to_ckey: OnceLock::new(),
         })
     }
 
+    // Index of the only CKey page that could contain the given CKey, found by
+    // binary search over the pages' sorted first keys
+    pub fn find_ckey_page(&self, ckey: &CKey) -> Option<usize> {
+        self.ckey_page_first_keys.partition_point(|first_key| first_key.0 <= ckey.0).checked_sub(1)
+    }
+
+    pub fn find_ekey_page(&self, ekey: &EKey) -> Option<usize> {
+        self.ekey_page_first_keys.partition_point(|first_key| first_key.0 <= ekey.0).checked_sub(1)
+    }
+
+    // The CKey's EKeys and decoded size, without building a CKeyEntry
+    fn find_ckey(&self, ckey: &CKey) -> Option<(&[EKey], u64)> {
+        match &self.backend {
+            EncodingBackend::Hashed { ckey_to_entry, .. } => {
+                ckey_to_entry.get(ckey).map(|entry| (entry.ekeys.as_slice(), entry.size))
+            },
+            EncodingBackend::Paged { ckey_pages, page_size_ckey, .. } => {
+                let page_start = self.find_ckey_page(ckey)? * page_size_ckey;
+                find_in_ckey_page(&ckey_pages[page_start .. page_start + page_size_ckey], ckey)
+            },
+        }
+    }
+
+    pub fn get_ckey_entry(&self, ckey: &CKey) -> Option<CKeyEntry> {
+        self.find_ckey(ckey).map(|(ekeys, size)| CKeyEntry { ekeys: ekeys.to_vec(), size })
+    }
+
+    pub fn get_ekey_spec(&self, ekey: &EKey) -> Option<EKeySpec> {
+        match &self.backend {
+            EncodingBackend::Hashed { ekey_to_spec, .. } => ekey_to_spec.get(ekey).cloned(),
+            EncodingBackend::Paged { ekey_pages, page_size_ekey, .. } => {
+                let page_start = self.find_ekey_page(ekey)? * page_size_ekey;
+                iter_ekey_page(&ekey_pages[page_start .. page_start + page_size_ekey])
+                    .find(|entry| entry.ekey == *ekey)
+                    .map(|entry| EKeySpec { espec_index: entry.espec_index, encoded_size: entry.encoded_size })
+            },
+        }
+    }
+
+    pub fn iter_ckey_entries(&self) -> Box<dyn Iterator<Item = (CKey, CKeyEntry)> + '_> {
+        match &self.backend {
+            EncodingBackend::Hashed { ckey_to_entry, .. } => {
+                Box::new(ckey_to_entry.iter().map(|(ckey, entry)| (ckey.clone(), entry.clone())))
+            },
+            EncodingBackend::Paged { ckey_pages, page_size_ckey, .. } => {
+                Box::new(ckey_pages.chunks(*page_size_ckey)
+                    .flat_map(iter_ckey_page)
+                    .map(|entry| (entry.ckey, CKeyEntry { ekeys: entry.ekeys, size: entry.size })))
+            },
+        }
+    }
+
     pub fn get_ekey_for_ckey(&self, ckey: &CKey) -> Option<&EKey> {
-        self.ckey_to_ekey.get(ckey)
+        self.find_ckey(ckey)?.0.first()
+    }
+
+    // A CKey can have several encodings, any of which may be the one that's
+    // actually present in the CDN's archives.
+    pub fn get_ekeys_for_ckey(&self, ckey: &CKey) -> Option<&[EKey]> {
+        self.find_ckey(ckey).map(|(ekeys, _)| ekeys)
+    }
+
+    // Builds a map of every EKey back to its CKey on first use, with either
+    // backend
+    pub fn get_ckey_for_ekey(&self, ekey: &EKey) -> Option<&CKey> {
+        let ekey_to_ckey = self.ekey_to_ckey.get_or_init(|| {
+            let mut ekey_to_ckey = HashMap::new();
+            for (ckey, entry) in self.iter_ckey_entries() {
+                for ekey in entry.ekeys {
+                    ekey_to_ckey.insert(ekey, ckey.clone());
+                }
+            }
+            ekey_to_ckey
+        });
+        ekey_to_ckey.get(ekey)
+    }
+
+    // The decoded size of the CKey's content
+    pub fn get_size_for_ckey(&self, ckey: &CKey) -> Option<u64> {
+        self.find_ckey(ckey).map(|(_, size)| size)
+    }
+
+    pub fn get_espec_for_ekey(&self, ekey: &EKey) -> Option<&ESpec> {
+        let spec = self.get_ekey_spec(ekey)?;
+        self.especs.get(spec.espec_index as usize)
+    }
+
+    pub fn get_encoded_size_for_ekey(&self, ekey: &EKey) -> Option<u64> {
+        self.get_ekey_spec(ekey).map(|spec| spec.encoded_size)
     }
 }
 
@@ -80,6 +357,172 @@ impl EncodingFile {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use md5::{Digest, Md5};
+    use crate::tact::blte::blte_wrap;
+
+    const TEST_PAGE_SIZE_KB: u16 = 1;
+
+    type TestCKeyEntry = (CKey, Vec<EKey>, u64);
+    type TestEKeyEntry = (EKey, u32, u64);
+
+    fn make_key(i: u32, salt: u8) -> [u8; 16] {
+        let mut key = [salt; 16];
+        key[..4].copy_from_slice(&i.to_be_bytes());
+        key
+    }
+
+    // Lays out entries into zero-padded pages, returning the page table and
+    // the page data
+    fn make_pages(entries: Vec<(Vec<u8>, Vec<u8>)>) -> (Vec<u8>, Vec<u8>) {
+        let page_size = TEST_PAGE_SIZE_KB as usize * 1024;
+        let mut pages: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        for (first_key, entry) in entries {
+            match pages.last_mut() {
+                Some((_, page)) if page.len() + entry.len() <= page_size => page.extend(entry),
+                _ => pages.push((first_key, entry)),
+            }
+        }
+        let (mut table, mut data) = (Vec::new(), Vec::new());
+        for (first_key, mut page) in pages {
+            page.resize(page_size, 0);
+            table.extend(first_key);
+            table.extend(Md5::digest(&page));
+            data.extend(page);
+        }
+        (table, data)
+    }
+
+    fn make_encoding_file(ckeys: &[TestCKeyEntry], ekeys: &[TestEKeyEntry], especs: &[&str]) -> Vec<u8> {
+        let data = make_encoding_data(ckeys, ekeys, especs);
+        blte_wrap(&data)
+    }
+
+    fn make_encoding_data(ckeys: &[TestCKeyEntry], ekeys: &[TestEKeyEntry], especs: &[&str]) -> Vec<u8> {
+        let mut ckeys = ckeys.to_vec();
+        ckeys.sort_by_key(|a| a.0.0);
+        let (ckey_table, ckey_pages) = make_pages(ckeys.iter().map(|(ckey, ekeys, size)| {
+            let mut entry = vec![ekeys.len() as u8];
+            entry.extend(&size.to_be_bytes()[3..]);
+            entry.extend(ckey.0);
+            for ekey in ekeys {
+                entry.extend(ekey.0);
+            }
+            (ckey.0.to_vec(), entry)
+        }).collect());
+
+        let mut ekeys = ekeys.to_vec();
+        ekeys.sort_by_key(|a| a.0.0);
+        let (ekey_table, ekey_pages) = make_pages(ekeys.iter().map(|(ekey, espec_index, size)| {
+            let mut entry = ekey.0.to_vec();
+            entry.extend(espec_index.to_be_bytes());
+            entry.extend(&size.to_be_bytes()[3..]);
+            (ekey.0.to_vec(), entry)
+        }).collect());
+
+        let mut espec_table = Vec::new();
+        for espec in especs {
+            espec_table.extend(espec.as_bytes());
+            espec_table.push(0);
+        }
+
+        let mut data = b"EN".to_vec();
+        data.extend([1, 16, 16]);
+        data.extend(TEST_PAGE_SIZE_KB.to_be_bytes());
+        data.extend(TEST_PAGE_SIZE_KB.to_be_bytes());
+        data.extend(((ckey_table.len() / 32) as u32).to_be_bytes());
+        data.extend(((ekey_table.len() / 32) as u32).to_be_bytes());
+        data.push(0);
+        data.extend((espec_table.len() as u32).to_be_bytes());
+        for part in [espec_table, ckey_table, ckey_pages, ekey_table, ekey_pages] {
+            data.extend(part);
+        }
+        data
+    }
+
+    fn make_test_entries(n: u32) -> (Vec<TestCKeyEntry>, Vec<TestEKeyEntry>) {
+        let mut ckeys = Vec::new();
+        let mut ekeys = Vec::new();
+        for i in 0..n {
+            let ekey = EKey(make_key(i, 0xee));
+            ckeys.push((CKey(make_key(i, 0xcc)), vec![ekey.clone()], 0x1_0000_0000 + i as u64));
+            ekeys.push((ekey, i % 2, 100 + i as u64));
+        }
+        (ckeys, ekeys)
+    }
+
+    #[test]
+    fn test_encoding_file_especs() {
+        let (ckeys, ekeys) = make_test_entries(100);
+        let data = make_encoding_file(&ckeys, &ekeys, &["n", "b:{256K*=z}"]);
+        let file = EncodingFile::parse(&data).unwrap();
+
+        assert_eq!(file.especs.len(), 2);
+        assert_eq!(file.iter_ckey_entries().count(), 100);
+        assert_eq!(file.get_ekey_for_ckey(&ckeys[42].0), Some(&ekeys[42].0));
+        assert_eq!(file.get_espec_for_ekey(&ekeys[0].0), Some(&ESpec::None));
+        assert_eq!(file.get_espec_for_ekey(&ekeys[1].0).unwrap().to_string(), "b:{256K*=z}");
+        assert_eq!(file.get_encoded_size_for_ekey(&ekeys[99].0), Some(199));
+    }
+
+    #[test]
+    fn test_encoding_file_verify_pages() {
+        let (ckeys, ekeys) = make_test_entries(100);
+        let data = make_encoding_data(&ckeys, &ekeys, &["n"]);
+        let file = EncodingFile::parse(&blte_wrap(&data)).unwrap();
+        assert!(file.ckey_page_first_keys.len() > 1);
+        let page = file.find_ckey_page(&ckeys[99].0).unwrap();
+        assert_eq!(page, file.ckey_page_first_keys.len() - 1);
+        assert_eq!(file.find_ckey_page(&CKey([0; 16])), None);
+
+        // flip a byte in the second CKey page
+        let mut corrupted = data.clone();
+        let offs = 22 + 2 + file.ckey_page_first_keys.len() * 32 + TEST_PAGE_SIZE_KB as usize * 1024 + 10;
+        corrupted[offs] ^= 0xff;
+        let result = EncodingFile::parse(&blte_wrap(&corrupted));
+        assert!(matches!(result, Err(Error::EncodingPageChecksumMismatch("CKey", 1))));
+
+        let truncated = &data[..data.len() - 1];
+        let result = EncodingFile::parse(&blte_wrap(truncated));
+        assert!(matches!(result, Err(Error::TruncatedEncodingFile)));
+    }
+
+    #[test]
+    fn test_encoding_file_paged() {
+        let (mut ckeys, ekeys) = make_test_entries(200);
+        ckeys[150].1.push(EKey(make_key(1234, 0xee)));
+        let data = make_encoding_file(&ckeys, &ekeys, &["n", "z"]);
+        let hashed = EncodingFile::parse(&data).unwrap();
+        let paged = EncodingFile::parse_paged(&data).unwrap();
+
+        for (ckey, _, _) in &ckeys {
+            assert_eq!(paged.get_ekeys_for_ckey(ckey), hashed.get_ekeys_for_ckey(ckey));
+            assert_eq!(paged.get_size_for_ckey(ckey), hashed.get_size_for_ckey(ckey));
+        }
+        for (ekey, _, _) in &ekeys {
+            assert_eq!(paged.get_espec_for_ekey(ekey), hashed.get_espec_for_ekey(ekey));
+            assert_eq!(paged.get_encoded_size_for_ekey(ekey), hashed.get_encoded_size_for_ekey(ekey));
+            assert_eq!(paged.get_ckey_for_ekey(ekey), hashed.get_ckey_for_ekey(ekey));
+        }
+        assert_eq!(paged.iter_ckey_entries().count(), 200);
+        assert_eq!(paged.get_ekey_for_ckey(&CKey([0xff; 16])), None);
+        assert_eq!(paged.get_ekey_for_ckey(&CKey([0; 16])), None);
+    }
+
+    #[test]
+    fn test_encoding_file_multiple_ekeys() {
+        let (mut ckeys, ekeys) = make_test_entries(10);
+        let extra_ekey = EKey(make_key(1234, 0xee));
+        ckeys[3].1.push(extra_ekey.clone());
+        let data = make_encoding_file(&ckeys, &ekeys, &["n"]);
+        let file = EncodingFile::parse(&data).unwrap();
+
+        assert_eq!(file.get_ekeys_for_ckey(&ckeys[3].0), Some([ekeys[3].0.clone(), extra_ekey.clone()].as_slice()));
+        assert_eq!(file.get_ekey_for_ckey(&ckeys[3].0), Some(&ekeys[3].0));
+        assert_eq!(file.get_size_for_ckey(&ckeys[3].0), Some(0x1_0000_0003));
+        assert_eq!(file.get_ckey_for_ekey(&extra_ekey), Some(&ckeys[3].0));
+        assert_eq!(file.get_ckey_for_ekey(&ekeys[7].0), Some(&ckeys[7].0));
+        assert_eq!(file.get_ckey_for_ekey(&NULL_EKEY), None);
+    }
 
     #[test]
     fn test_encoding_file() {
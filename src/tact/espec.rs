@@ -0,0 +1,334 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+// An encoding specification, describing how a file's data was (or should be)
+// chunked and compressed into a BLTE. See https://wowdev.wiki/BLTE#ESpec
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ESpec {
+    None,
+    Zlib { level: Option<u8>, bits: Option<ZlibBits> },
+    Blocks(Vec<ESpecBlock>),
+    Encrypted { key_name: u64, iv: Vec<u8>, spec: Box<ESpec> },
+    BCPack { bcn: u32 },
+    GDeflate { level: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZlibBits {
+    Window(u8),
+    Mpq,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ESpecBlock {
+    // None for a `*` block, which covers the rest of the data
+    pub size: Option<usize>,
+    pub count: BlockCount,
+    pub spec: ESpec,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockCount {
+    Once,
+    Times(u32),
+    UntilEnd,
+}
+
+// How deeply 'b' and 'e' specs may nest inside each other before we give up,
+// since each one recurses
+const MAX_ESPEC_DEPTH: usize = 16;
+
+struct ESpecParser<'a> {
+    src: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ESpecParser<'a> {
+    fn new(src: &'a str) -> Self {
+        ESpecParser { src, bytes: src.as_bytes(), pos: 0 }
+    }
+
+    fn error(&self) -> Error {
+        Error::InvalidESpec(self.src.to_string())
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<u8, Error> {
+        let c = self.peek().ok_or_else(|| self.error())?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        if self.next()? != c {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<u64, Error> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.src[start..self.pos].parse().map_err(|_| self.error())
+    }
+
+    // Parses a number, failing if it doesn't fit in T rather than truncating
+    fn parse_int<T: TryFrom<u64>>(&mut self) -> Result<T, Error> {
+        T::try_from(self.parse_number()?).map_err(|_| self.error())
+    }
+
+    fn parse_hex(&mut self) -> Result<&'a str, Error> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        if self.pos == start || !(self.pos - start).is_multiple_of(2) {
+            return Err(self.error());
+        }
+        Ok(&self.src[start..self.pos])
+    }
+
+    // Parses a single numeric argument, which may optionally be wrapped in
+    // braces (e.g. both `c:4` and `c:{4}`)
+    fn parse_number_arg<T: TryFrom<u64>>(&mut self) -> Result<T, Error> {
+        self.expect(b':')?;
+        if self.eat(b'{') {
+            let n = self.parse_int()?;
+            self.expect(b'}')?;
+            Ok(n)
+        } else {
+            self.parse_int()
+        }
+    }
+
+    fn parse_spec(&mut self, depth: usize) -> Result<ESpec, Error> {
+        if depth > MAX_ESPEC_DEPTH {
+            return Err(self.error());
+        }
+        match self.next()? {
+            b'n' => Ok(ESpec::None),
+            b'z' => {
+                let (mut level, mut bits) = (None, None);
+                if self.eat(b':') {
+                    if self.eat(b'{') {
+                        level = Some(self.parse_int()?);
+                        self.expect(b',')?;
+                        if self.eat(b'm') {
+                            self.expect(b'p')?;
+                            self.expect(b'q')?;
+                            bits = Some(ZlibBits::Mpq);
+                        } else {
+                            bits = Some(ZlibBits::Window(self.parse_int()?));
+                        }
+                        self.expect(b'}')?;
+                    } else {
+                        level = Some(self.parse_int()?);
+                    }
+                }
+                Ok(ESpec::Zlib { level, bits })
+            },
+            b'e' => {
+                self.expect(b':')?;
+                self.expect(b'{')?;
+                let key_name_str = self.parse_hex()?;
+                let key_name = u64::from_str_radix(key_name_str, 16).map_err(|_| self.error())?;
+                self.expect(b',')?;
+                let iv_str = self.parse_hex()?;
+                let iv = (0..iv_str.len()).step_by(2)
+                    .map(|i| u8::from_str_radix(&iv_str[i..i+2], 16).map_err(|_| self.error()))
+                    .collect::<Result<Vec<u8>, Error>>()?;
+                self.expect(b',')?;
+                let spec = Box::new(self.parse_spec(depth + 1)?);
+                self.expect(b'}')?;
+                Ok(ESpec::Encrypted { key_name, iv, spec })
+            },
+            b'c' => Ok(ESpec::BCPack { bcn: self.parse_number_arg()? }),
+            b'g' => Ok(ESpec::GDeflate { level: self.parse_number_arg()? }),
+            b'b' => {
+                self.expect(b':')?;
+                let mut blocks = Vec::new();
+                if self.eat(b'{') {
+                    loop {
+                        blocks.push(self.parse_block(depth + 1)?);
+                        if !self.eat(b',') {
+                            break;
+                        }
+                    }
+                    self.expect(b'}')?;
+                } else {
+                    blocks.push(self.parse_block(depth + 1)?);
+                }
+                Ok(ESpec::Blocks(blocks))
+            },
+            _ => Err(self.error()),
+        }
+    }
+
+    fn parse_block(&mut self, depth: usize) -> Result<ESpecBlock, Error> {
+        let (size, count) = if self.eat(b'*') {
+            (None, BlockCount::Once)
+        } else {
+            let size: usize = self.parse_int()?;
+            let unit = if self.eat(b'K') {
+                1024
+            } else if self.eat(b'M') {
+                1024 * 1024
+            } else {
+                1
+            };
+            let size = size.checked_mul(unit).ok_or_else(|| self.error())?;
+            let count = if self.eat(b'*') {
+                if matches!(self.peek(), Some(b'0'..=b'9')) {
+                    BlockCount::Times(self.parse_int()?)
+                } else {
+                    BlockCount::UntilEnd
+                }
+            } else {
+                BlockCount::Once
+            };
+            (Some(size), count)
+        };
+        self.expect(b'=')?;
+        let spec = self.parse_spec(depth)?;
+        Ok(ESpecBlock { size, count, spec })
+    }
+}
+
+impl FromStr for ESpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ESpecParser::new(s.trim());
+        let spec = parser.parse_spec(0)?;
+        if parser.peek().is_some() {
+            return Err(parser.error());
+        }
+        Ok(spec)
+    }
+}
+
+fn format_size(size: usize) -> String {
+    if size != 0 && size.is_multiple_of(1024 * 1024) {
+        format!("{}M", size / (1024 * 1024))
+    } else if size != 0 && size.is_multiple_of(1024) {
+        format!("{}K", size / 1024)
+    } else {
+        format!("{}", size)
+    }
+}
+
+impl fmt::Display for ESpecBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size {
+            None => write!(f, "*")?,
+            Some(size) => write!(f, "{}", format_size(size))?,
+        }
+        match self.count {
+            BlockCount::Once => {},
+            BlockCount::Times(n) => write!(f, "*{}", n)?,
+            BlockCount::UntilEnd => write!(f, "*")?,
+        }
+        write!(f, "={}", self.spec)
+    }
+}
+
+impl fmt::Display for ESpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ESpec::None => write!(f, "n"),
+            ESpec::Zlib { level: None, .. } => write!(f, "z"),
+            ESpec::Zlib { level: Some(level), bits: None } => write!(f, "z:{}", level),
+            ESpec::Zlib { level: Some(level), bits: Some(ZlibBits::Window(bits)) } => write!(f, "z:{{{},{}}}", level, bits),
+            ESpec::Zlib { level: Some(level), bits: Some(ZlibBits::Mpq) } => write!(f, "z:{{{},mpq}}", level),
+            ESpec::Blocks(blocks) => {
+                let blocks: Vec<String> = blocks.iter().map(|block| block.to_string()).collect();
+                write!(f, "b:{{{}}}", blocks.join(","))
+            },
+            ESpec::Encrypted { key_name, iv, spec } => {
+                let iv: String = iv.iter().map(|b| format!("{:02X}", b)).collect();
+                write!(f, "e:{{{:016X},{},{}}}", key_name, iv, spec)
+            },
+            ESpec::BCPack { bcn } => write!(f, "c:{{{}}}", bcn),
+            ESpec::GDeflate { level } => write!(f, "g:{{{}}}", level),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_espec_parse() {
+        let spec: ESpec = "b:{16K*565=z,1807K=z:6,*=n}".parse().unwrap();
+        assert_eq!(spec, ESpec::Blocks(vec![
+            ESpecBlock { size: Some(16 * 1024), count: BlockCount::Times(565), spec: ESpec::Zlib { level: None, bits: None } },
+            ESpecBlock { size: Some(1807 * 1024), count: BlockCount::Once, spec: ESpec::Zlib { level: Some(6), bits: None } },
+            ESpecBlock { size: None, count: BlockCount::Once, spec: ESpec::None },
+        ]));
+        assert_eq!(spec.to_string(), "b:{16K*565=z,1807K=z:6,*=n}");
+
+        let spec: ESpec = "b:{256K*=z}".parse().unwrap();
+        assert_eq!(spec.to_string(), "b:{256K*=z}");
+        assert!("b:{256K*=q}".parse::<ESpec>().is_err());
+        assert!("z trailing".parse::<ESpec>().is_err());
+    }
+
+    #[test]
+    fn test_espec_parse_encrypted() {
+        let s = "b:{16K=n,*=e:{237DA26C65073F42,06FC152E,z:{9,mpq}}}";
+        let spec: ESpec = s.parse().unwrap();
+        let ESpec::Blocks(blocks) = &spec else {
+            panic!("expected a block spec");
+        };
+        assert_eq!(blocks[1].spec, ESpec::Encrypted {
+            key_name: 0x237DA26C65073F42,
+            iv: vec![0x06, 0xFC, 0x15, 0x2E],
+            spec: Box::new(ESpec::Zlib { level: Some(9), bits: Some(ZlibBits::Mpq) }),
+        });
+        assert_eq!(spec.to_string(), s);
+        assert_eq!("z:{6,15}".parse::<ESpec>().unwrap(), ESpec::Zlib { level: Some(6), bits: Some(ZlibBits::Window(15)) });
+        assert_eq!("c:{4}".parse::<ESpec>().unwrap(), ESpec::BCPack { bcn: 4 });
+    }
+
+    #[test]
+    fn test_espec_parse_out_of_range() {
+        assert_eq!("z:255".parse::<ESpec>().unwrap(), ESpec::Zlib { level: Some(255), bits: None });
+        assert!("z:{300,15}".parse::<ESpec>().is_err());
+        assert!("z:300".parse::<ESpec>().is_err());
+        assert!("z:{9,256}".parse::<ESpec>().is_err());
+        assert!("c:{4294967296}".parse::<ESpec>().is_err());
+        assert!("g:4294967296".parse::<ESpec>().is_err());
+        assert!("b:{16K*4294967296=z}".parse::<ESpec>().is_err());
+        assert!("b:{18014398509481984M=n}".parse::<ESpec>().is_err());
+        assert!("b:{99999999999999999999=n}".parse::<ESpec>().is_err());
+    }
+
+    #[test]
+    fn test_espec_parse_nesting_depth() {
+        let nested = |depth: usize| format!("{}n{}", "b:{*=".repeat(depth), "}".repeat(depth));
+        assert!(nested(MAX_ESPEC_DEPTH).parse::<ESpec>().is_ok());
+        assert!(matches!(nested(MAX_ESPEC_DEPTH + 1).parse::<ESpec>(), Err(Error::InvalidESpec(_))));
+        // deep enough to overflow the stack without the limit
+        assert!(nested(100_000).parse::<ESpec>().is_err());
+        let encrypted = format!("{}n{}", "e:{01,02,".repeat(MAX_ESPEC_DEPTH + 1), "}".repeat(MAX_ESPEC_DEPTH + 1));
+        assert!(matches!(encrypted.parse::<ESpec>(), Err(Error::InvalidESpec(_))));
+    }
+}
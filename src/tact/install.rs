@@ -0,0 +1,113 @@
+use std::ffi::CString;
+
+use deku::{DekuRead, DekuContainerRead};
+
+use crate::error::Error;
+use crate::tact::blte::decode_blte;
+use crate::tact::common::{get_tag_sizes, get_tagged_entry_indices, CKey, RawTag, Tag};
+
+// The install manifest, listing the files a client installs outside of its
+// CASC storage. See https://wowdev.wiki/TACT#Install_manifest
+#[derive(Clone, Debug)]
+pub struct InstallManifest {
+    pub tags: Vec<Tag>,
+    pub entries: Vec<InstallEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct InstallEntry {
+    pub name: String,
+    pub ckey: CKey,
+    pub size: u32,
+}
+
+#[derive(DekuRead, Debug)]
+struct RawInstallEntry {
+    name: CString,
+    ckey: CKey,
+    #[deku(endian = "big")]
+    size: u32,
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(magic = b"IN")]
+struct InstallManifestData {
+    #[deku(assert_eq = "1")]
+    _version: u8,
+    #[deku(assert_eq = "16")]
+    _hash_size: u8,
+    #[deku(endian = "big")]
+    _num_tags: u16,
+    #[deku(endian = "big")]
+    _num_entries: u32,
+    #[deku(count = "_num_tags", ctx = "*_num_entries as usize")]
+    tags: Vec<RawTag>,
+    #[deku(count = "_num_entries")]
+    entries: Vec<RawInstallEntry>,
+}
+
+impl InstallManifest {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let decode = decode_blte(data)?;
+        let (_, manifest) = InstallManifestData::from_bytes((&decode, 0))?;
+        Ok(InstallManifest {
+            tags: manifest.tags.into_iter().map(Tag::from).collect(),
+            entries: manifest.entries.into_iter()
+                .map(|entry| InstallEntry {
+                    name: entry.name.to_string_lossy().into_owned(),
+                    ckey: entry.ckey,
+                    size: entry.size,
+                })
+                .collect(),
+        })
+    }
+
+    // Finds the entries which have every one of the named tags
+    pub fn get_entries_with_tags(&self, tag_names: &[&str]) -> Result<Vec<&InstallEntry>, Error> {
+        let indices = get_tagged_entry_indices(&self.tags, tag_names, self.entries.len())?;
+        Ok(indices.into_iter().map(|i| &self.entries[i]).collect())
+    }
+
+    // The total install size of each tag's files
+    pub fn get_tag_sizes(&self) -> Vec<(String, u64)> {
+        get_tag_sizes(&self.tags, self.entries.len(), |i| self.entries[i].size as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tact::blte::blte_wrap;
+
+    #[test]
+    fn test_install_manifest() {
+        let mut data = b"IN".to_vec();
+        data.extend([1, 16]);
+        data.extend(2u16.to_be_bytes());
+        data.extend(3u32.to_be_bytes());
+        data.extend(b"Windows\0");
+        data.extend(1u16.to_be_bytes());
+        data.push(0b1100_0000);
+        data.extend(b"OSX\0");
+        data.extend(1u16.to_be_bytes());
+        data.push(0b0010_0000);
+        for (i, name) in ["Wow.exe", "Data\\a.dat", "World of Warcraft.app"].iter().enumerate() {
+            data.extend(name.as_bytes());
+            data.push(0);
+            data.extend([i as u8; 16]);
+            data.extend((100 * (i as u32 + 1)).to_be_bytes());
+        }
+        let data = blte_wrap(&data);
+
+        let manifest = InstallManifest::parse(&data).unwrap();
+        assert_eq!(manifest.entries.len(), 3);
+        assert_eq!(manifest.entries[1].name, "Data\\a.dat");
+        assert_eq!(manifest.entries[2].ckey, CKey([2; 16]));
+        let windows: Vec<&str> = manifest.get_entries_with_tags(&["Windows"]).unwrap()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(windows, vec!["Wow.exe", "Data\\a.dat"]);
+        assert_eq!(manifest.get_tag_sizes(), vec![("Windows".into(), 300), ("OSX".into(), 300)]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+
+pub type TactKey = [u8; 16];
+
+// A set of named TACT encryption keys, as used by BLTE 'E' frames. Key names
+// are the 64-bit lookup values that appear (little-endian) in each frame.
+#[derive(Clone, Debug, Default)]
+pub struct KeyStore {
+    pub keys: HashMap<u64, TactKey>,
+}
+
+impl KeyStore {
+    // Parses the community key list format: one `keyname keyhex` pair per
+    // line, where keyname is 16 hex digits and keyhex is 32 hex digits. Any
+    // trailing columns (build names, descriptions, etc.) are ignored.
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut keys = HashMap::new();
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(name_str), Some(key_str)) = (fields.next(), fields.next()) else {
+                return Err(Error::InvalidTactKey(line.to_string()));
+            };
+            let name = u64::from_str_radix(name_str, 16)
+                .map_err(|_| Error::InvalidTactKey(line.to_string()))?;
+            let key = parse_key_hex(key_str)
+                .ok_or_else(|| Error::InvalidTactKey(line.to_string()))?;
+            keys.insert(name, key);
+        }
+        Ok(KeyStore { keys })
+    }
+
+    pub fn insert(&mut self, name: u64, key: TactKey) {
+        self.keys.insert(name, key);
+    }
+
+    pub fn get(&self, name: u64) -> Option<&TactKey> {
+        self.keys.get(&name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+fn parse_key_hex(s: &str) -> Option<TactKey> {
+    if s.len() != 32 || !s.is_ascii() {
+        return None;
+    }
+    let mut key = [0; 16];
+    for (i, b) in key.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[i*2..i*2+2], 16).ok()?;
+    }
+    Some(key)
+}
+
+fn salsa20_quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+    x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+    x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+    x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+fn salsa20_block(input: &[u32; 16]) -> [u8; 64] {
+    let mut x = *input;
+    for _ in 0..10 {
+        salsa20_quarter_round(&mut x, 0, 4, 8, 12);
+        salsa20_quarter_round(&mut x, 5, 9, 13, 1);
+        salsa20_quarter_round(&mut x, 10, 14, 2, 6);
+        salsa20_quarter_round(&mut x, 15, 3, 7, 11);
+        salsa20_quarter_round(&mut x, 0, 1, 2, 3);
+        salsa20_quarter_round(&mut x, 5, 6, 7, 4);
+        salsa20_quarter_round(&mut x, 10, 11, 8, 9);
+        salsa20_quarter_round(&mut x, 15, 12, 13, 14);
+    }
+    let mut out = [0; 64];
+    for i in 0..16 {
+        out[i*4..i*4+4].copy_from_slice(&x[i].wrapping_add(input[i]).to_le_bytes());
+    }
+    out
+}
+
+// Salsa20/20 with a 128-bit key (the "expand 16-byte k" variant), which is
+// what TACT uses for 'S' encrypted frames. Encryption and decryption are the
+// same operation.
+pub fn salsa20_apply(key: &TactKey, nonce: &[u8; 8], data: &mut [u8]) {
+    let word = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    let tau = b"expand 16-byte k";
+    let mut state = [0u32; 16];
+    state[0] = word(&tau[0..4]);
+    state[5] = word(&tau[4..8]);
+    state[10] = word(&tau[8..12]);
+    state[15] = word(&tau[12..16]);
+    for i in 0..4 {
+        state[1 + i] = word(&key[i*4..]);
+        state[11 + i] = word(&key[i*4..]);
+    }
+    state[6] = word(&nonce[0..4]);
+    state[7] = word(&nonce[4..8]);
+
+    for (counter, block) in data.chunks_mut(64).enumerate() {
+        state[8] = counter as u32;
+        state[9] = ((counter as u64) >> 32) as u32;
+        let keystream = salsa20_block(&state);
+        for (b, k) in block.iter_mut().zip(keystream.iter()) {
+            *b ^= k;
+        }
+    }
+}
+
+// Plain RC4, used by the older 'A' encrypted frames. Encryption and
+// decryption are the same operation.
+pub fn arc4_apply(key: &[u8], data: &mut [u8]) {
+    let mut s: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j: u8 = 0;
+    for i in 0..256 {
+        j = j.wrapping_add(s[i]).wrapping_add(key[i % key.len()]);
+        s.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    for b in data.iter_mut() {
+        i = i.wrapping_add(1);
+        j = j.wrapping_add(s[i as usize]);
+        s.swap(i as usize, j as usize);
+        *b ^= s[s[i as usize].wrapping_add(s[j as usize]) as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_store_parse() {
+        let data = "# comment\nFA505078126ACB3E BDC51862ABED79B2DE48C8E7E66C6200 extra stuff\n\nff813f7d062ac0bc aa0b5c77f088ccc2d39049bd267f066d\n";
+        let store = KeyStore::parse(data).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(0xFA505078126ACB3E).unwrap()[0], 0xBD);
+        assert_eq!(store.get(0xFF813F7D062AC0BC).unwrap()[15], 0x6D);
+        assert!(KeyStore::parse("FA505078126ACB3E nothex").is_err());
+    }
+
+    #[test]
+    fn test_salsa20_vector() {
+        // ECRYPT Salsa20 128-bit key, set 1, vector 0
+        let mut key = [0; 16];
+        key[0] = 0x80;
+        let mut data = [0; 16];
+        salsa20_apply(&key, &[0; 8], &mut data);
+        assert_eq!(data, [0x4D, 0xFA, 0x5E, 0x48, 0x1D, 0xA2, 0x3E, 0xA0, 0x9A, 0x31, 0x02, 0x20, 0x50, 0x85, 0x99, 0x36]);
+    }
+
+    #[test]
+    fn test_arc4_vector() {
+        let mut data = *b"Plaintext";
+        arc4_apply(b"Key", &mut data);
+        assert_eq!(data, [0xBB, 0xF3, 0x16, 0xE8, 0xD9, 0x40, 0xAF, 0x0A, 0xD3]);
+    }
+}
@@ -1,39 +1,174 @@
+use std::fmt;
+use std::str::FromStr;
+
 use crate::error::Error;
 
-#[derive(Clone)]
+// The type of a BPSV column, from its `Name!TYPE:size` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    String(usize),
+    // hex digits encoding `size` bytes
+    Hex(usize),
+    // a decimal number `size` bytes wide
+    Dec(usize),
+}
+
+impl FromStr for FieldType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidManifestHeader(s.to_string());
+        let (type_name, size) = s.split_once(':').ok_or_else(invalid)?;
+        let size = size.parse().map_err(|_| invalid())?;
+        match type_name.to_ascii_uppercase().as_str() {
+            "STRING" => Ok(FieldType::String(size)),
+            "HEX" => Ok(FieldType::Hex(size)),
+            "DEC" => Ok(FieldType::Dec(size)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldType::String(size) => write!(f, "STRING:{}", size),
+            FieldType::Hex(size) => write!(f, "HEX:{}", size),
+            FieldType::Dec(size) => write!(f, "DEC:{}", size),
+        }
+    }
+}
+
+impl FieldType {
+    // Empty values are allowed for every type
+    fn is_valid(&self, value: &str) -> bool {
+        match self {
+            _ if value.is_empty() => true,
+            FieldType::String(_) => true,
+            FieldType::Hex(size) => value.len() == size * 2 && value.bytes().all(|b| b.is_ascii_hexdigit()),
+            FieldType::Dec(_) => value.parse::<u64>().is_ok(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestField {
+    pub name: String,
+    pub field_type: FieldType,
+    // the type as the header spelled it (e.g. `String:0`), so that it's
+    // written back the same way
+    type_spelling: Option<String>,
+}
+
+impl ManifestField {
+    pub fn new(name: &str, field_type: FieldType) -> Self {
+        ManifestField {
+            name: name.to_string(),
+            field_type,
+            type_spelling: None,
+        }
+    }
+}
+
+impl fmt::Display for ManifestField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.type_spelling {
+            Some(spelling) => write!(f, "{}!{}", self.name, spelling),
+            None => write!(f, "{}!{}", self.name, self.field_type),
+        }
+    }
+}
+
+// A BPSV (pipe-separated values) manifest, like the patch server's versions
+// and cdns files. See https://wowdev.wiki/TACT#Ribbit
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Manifest {
-    pub field_names: Vec<String>,
+    pub fields: Vec<ManifestField>,
     pub rows: Vec<Vec<String>>,
+    // from the `## seqn = N` comment, which increases with every change
+    pub seqn: Option<u64>,
 }
 
 impl Manifest {
+    pub fn new(fields: Vec<ManifestField>, seqn: Option<u64>) -> Self {
+        Manifest {
+            fields,
+            rows: Vec::new(),
+            seqn,
+        }
+    }
+
     pub fn parse(data: &[u8]) -> Result<Self, Error> {
-        let body = std::str::from_utf8(data).unwrap();
-        let mut lines = body.lines();
+        let body = std::str::from_utf8(data).map_err(|e| {
+            let line = data[..e.valid_up_to()].iter().filter(|&&b| b == b'\n').count() + 1;
+            Error::InvalidManifestLine(line)
+        })?;
+        let mut lines = body.lines().enumerate();
 
-        let header = lines.next().unwrap();
-        let mut field_names = Vec::new();
+        let (_, header) = lines.next().ok_or_else(|| Error::InvalidManifestHeader(String::new()))?;
+        let mut fields = Vec::new();
         for field_def in header.split('|') {
-            let (field_name, _field_type) = field_def.split_once('!').unwrap();
-            field_names.push(field_name.to_string());
+            let (name, field_type) = field_def.split_once('!')
+                .ok_or_else(|| Error::InvalidManifestHeader(field_def.to_string()))?;
+            fields.push(ManifestField {
+                name: name.to_string(),
+                field_type: field_type.parse()?,
+                type_spelling: Some(field_type.to_string()),
+            });
         }
 
-        let mut rows = Vec::new();
-        for line in lines {
+        let mut manifest = Manifest::new(fields, None);
+        for (i, line) in lines {
+            if let Some(comment) = line.strip_prefix("##") {
+                if let Some((key, value)) = comment.split_once('=') {
+                    if key.trim() == "seqn" {
+                        manifest.seqn = Some(value.trim().parse().map_err(|_| Error::InvalidManifestLine(i + 1))?);
+                    }
+                }
+                continue;
+            }
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
-            rows.push(line.split('|').map(|s| s.to_string()).collect());
+            manifest.push_row(line.split('|').map(|s| s.to_string()).collect())
+                .map_err(|_| Error::InvalidManifestLine(i + 1))?;
         }
+        Ok(manifest)
+    }
+
+    // Adds a row, checking it has a valid value for every field
+    pub fn push_row(&mut self, row: Vec<String>) -> Result<(), Error> {
+        let valid = row.len() == self.fields.len()
+            && self.fields.iter().zip(&row).all(|(field, value)| field.field_type.is_valid(value));
+        if !valid {
+            return Err(Error::InvalidManifestRow(row.join("|")));
+        }
+        self.rows.push(row);
+        Ok(())
+    }
 
-        Ok(Manifest {
-            field_names,
-            rows,
-        })
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = self.fields.iter()
+            .map(|field| field.to_string())
+            .collect::<Vec<String>>()
+            .join("|");
+        result.push('\n');
+        if let Some(seqn) = self.seqn {
+            result.push_str(&format!("## seqn = {}\n", seqn));
+        }
+        for row in &self.rows {
+            result.push_str(&row.join("|"));
+            result.push('\n');
+        }
+        result.into_bytes()
     }
 
     pub fn get_field_index(&self, needle: &str) -> Option<usize> {
-        self.field_names.iter().position(|haystack| haystack == needle)
+        self.fields.iter().position(|haystack| haystack.name == needle)
+    }
+
+    pub fn get_field_type(&self, field: &str) -> Option<FieldType> {
+        Some(self.fields[self.get_field_index(field)?].field_type)
     }
 
     pub fn get_field(&self, row: usize, field: &str) -> Option<&str> {
@@ -42,8 +177,90 @@ impl Manifest {
         Some(row.get(field_index)?.as_str())
     }
 
+    // Returns the decoded bytes of a HEX field, or None if the field is
+    // missing, empty or not a HEX field
+    pub fn get_hex_field(&self, row: usize, field: &str) -> Option<Vec<u8>> {
+        let FieldType::Hex(_) = self.get_field_type(field)? else {
+            return None;
+        };
+        let value = self.get_field(row, field)?;
+        if value.is_empty() {
+            return None;
+        }
+        // rows are public, so the value may not have been validated
+        value.as_bytes().chunks(2)
+            .map(|pair| match pair {
+                [hi, lo] => Some((char::from(*hi).to_digit(16)? * 16 + char::from(*lo).to_digit(16)?) as u8),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Returns the value of a DEC field, or None if the field is missing,
+    // empty or not a DEC field
+    pub fn get_dec_field(&self, row: usize, field: &str) -> Option<u64> {
+        let FieldType::Dec(_) = self.get_field_type(field)? else {
+            return None;
+        };
+        self.get_field(row, field)?.parse().ok()
+    }
+
     pub fn find_row(&self, field: &str, value: &str) -> Option<usize> {
         let field_index = self.get_field_index(field)?;
         self.rows.iter().position(|row| row[field_index] == value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSIONS: &str = "Region!STRING:0|BuildConfig!HEX:16|CDNConfig!HEX:16|KeyRing!HEX:16|BuildId!DEC:4|VersionsName!String:0|ProductConfig!HEX:16
+## seqn = 2241282
+us|f4b2ab2a4a4f6c7a8d5d0fc3b0e1a9b2|9e1a4e4d4b2c9a1f6e0e2f1b1f4a0c6d||54261|1.15.2.54261|53020d32e1a25648c8e1eafd5771935f
+eu|f4b2ab2a4a4f6c7a8d5d0fc3b0e1a9b2|9e1a4e4d4b2c9a1f6e0e2f1b1f4a0c6d||54261|1.15.2.54261|53020d32e1a25648c8e1eafd5771935f
+";
+
+    #[test]
+    fn test_manifest() {
+        let manifest = Manifest::parse(VERSIONS.as_bytes()).unwrap();
+        assert_eq!(manifest.seqn, Some(2241282));
+        assert_eq!(manifest.rows.len(), 2);
+        assert_eq!(manifest.get_field_type("BuildConfig"), Some(FieldType::Hex(16)));
+        assert_eq!(manifest.get_field_type("VersionsName"), Some(FieldType::String(0)));
+
+        let row = manifest.find_row("Region", "eu").unwrap();
+        assert_eq!(manifest.get_field(row, "VersionsName"), Some("1.15.2.54261"));
+        assert_eq!(manifest.get_dec_field(row, "BuildId"), Some(54261));
+        assert_eq!(manifest.get_hex_field(row, "BuildConfig").unwrap()[..2], [0xf4, 0xb2]);
+        assert_eq!(manifest.get_hex_field(row, "KeyRing"), None);
+        assert_eq!(manifest.get_dec_field(row, "Region"), None);
+
+        // types are written back the way they were spelled
+        assert_eq!(manifest.to_bytes(), VERSIONS.as_bytes());
+        let roundtrip = Manifest::parse(&manifest.to_bytes()).unwrap();
+        assert_eq!(roundtrip, manifest);
+
+        // rows can be edited directly, so hex values aren't always valid
+        let mut edited = manifest.clone();
+        edited.rows[row][1] = "f4b".into();
+        assert_eq!(edited.get_hex_field(row, "BuildConfig"), None);
+        edited.rows[row][1] = "f\u{e9}".into();
+        assert_eq!(edited.get_hex_field(row, "BuildConfig"), None);
+    }
+
+    #[test]
+    fn test_manifest_errors() {
+        assert!(matches!(Manifest::parse(b"Region!STRING:0|BuildId\nus|1\n"), Err(Error::InvalidManifestHeader(_))));
+        assert!(matches!(Manifest::parse(b"Region!BLOB:0\nus\n"), Err(Error::InvalidManifestHeader(_))));
+        assert!(matches!(Manifest::parse(b"Region!STRING:0|BuildId!DEC:4\nus|abc\n"), Err(Error::InvalidManifestLine(2))));
+        assert!(matches!(Manifest::parse(b"Region!STRING:0|Key!HEX:2\nus|abc\n"), Err(Error::InvalidManifestLine(2))));
+        assert!(matches!(Manifest::parse(b"Region!STRING:0\n## seqn = 1\nus|eu\n"), Err(Error::InvalidManifestLine(3))));
+        assert!(matches!(Manifest::parse(b"Region!STRING:0\n\xff\n"), Err(Error::InvalidManifestLine(2))));
+
+        let mut manifest = Manifest::new(vec![ManifestField::new("Region", FieldType::String(0))], Some(1));
+        manifest.push_row(vec!["us".into()]).unwrap();
+        assert!(manifest.push_row(vec![]).is_err());
+        assert_eq!(manifest.to_bytes(), b"Region!STRING:0\n## seqn = 1\nus\n");
+    }
+}
@@ -3,4 +3,11 @@ pub mod archive;
 pub mod root;
 pub mod encoding;
 pub mod common;
+pub mod config;
 pub mod blte;
+pub mod keys;
+pub mod espec;
+pub mod patch;
+pub mod install;
+pub mod download;
+pub mod size;
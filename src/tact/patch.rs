@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use deku::{DekuRead, DekuContainerRead};
+use md5::{Digest, Md5};
+
+use crate::error::Error;
+use crate::tact::blte::decode_blte;
+use crate::tact::common::{CKey, EKey};
+use crate::tact::espec::ESpec;
+
+// The patch manifest, listing for each file which older versions of it can be
+// patched into the current one, and with which patch blobs. See
+// https://wowdev.wiki/TACT#Patch_manifest
+#[derive(Clone, Debug)]
+pub struct PatchManifest {
+    pub version: u8,
+    pub flags: u8,
+    pub encoding_ckey: CKey,
+    pub encoding_ekey: EKey,
+    pub encoding_decoded_size: u32,
+    pub encoding_encoded_size: u32,
+    pub encoding_espec: ESpec,
+    pub entries: Vec<PatchFileEntry>,
+    ckey_to_entry_index: HashMap<CKey, usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PatchFileEntry {
+    pub target_ckey: CKey,
+    pub decoded_size: u64,
+    pub patches: Vec<PatchRecord>,
+}
+
+// A patch blob which turns the file with `source_ekey` into the target file
+#[derive(DekuRead, Clone, Debug)]
+pub struct PatchRecord {
+    pub source_ekey: EKey,
+    #[deku(endian = "big", bytes = 5)]
+    pub source_decoded_size: u64,
+    pub patch_ekey: EKey,
+    #[deku(endian = "big")]
+    pub patch_size: u32,
+    pub order: u8,
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(magic = b"PA")]
+struct PatchManifestHeader {
+    pub version: u8,
+    #[deku(assert_eq = "16")]
+    pub _file_key_size: u8,
+    #[deku(assert_eq = "16")]
+    pub _old_key_size: u8,
+    #[deku(assert_eq = "16")]
+    pub _patch_key_size: u8,
+    pub block_size_bits: u8,
+    #[deku(endian = "big")]
+    pub block_count: u16,
+    pub flags: u8,
+    pub encoding_ckey: CKey,
+    pub encoding_ekey: EKey,
+    #[deku(endian = "big")]
+    pub encoding_decoded_size: u32,
+    #[deku(endian = "big")]
+    pub encoding_encoded_size: u32,
+    pub _encoding_espec_size: u8,
+    #[deku(count = "_encoding_espec_size")]
+    pub encoding_espec: Vec<u8>,
+}
+
+#[derive(DekuRead, Debug)]
+struct PatchBlockIndexEntry {
+    pub _last_file_ckey: CKey,
+    pub checksum: [u8; 16],
+    #[deku(endian = "big")]
+    pub offset: u32,
+}
+
+#[derive(DekuRead, Debug)]
+struct PatchFileRecord {
+    pub _num_patches: u8,
+    pub target_ckey: CKey,
+    #[deku(endian = "big", bytes = 5)]
+    pub decoded_size: u64,
+    #[deku(count = "_num_patches")]
+    pub patches: Vec<PatchRecord>,
+}
+
+impl PatchManifest {
+    // Accepts the manifest either raw or BLTE encoded
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let decoded;
+        let data = if data.starts_with(b"BLTE") {
+            decoded = decode_blte(data)?;
+            &decoded
+        } else {
+            data
+        };
+
+        let ((mut rest, _), header) = PatchManifestHeader::from_bytes((data, 0))?;
+        let encoding_espec = std::str::from_utf8(&header.encoding_espec)
+            .map_err(|_| Error::InvalidESpec(String::from_utf8_lossy(&header.encoding_espec).into_owned()))?
+            .parse()?;
+
+        let mut blocks = Vec::with_capacity(header.block_count as usize);
+        for _ in 0..header.block_count {
+            let ((new_rest, _), block) = PatchBlockIndexEntry::from_bytes((rest, 0))?;
+            rest = new_rest;
+            blocks.push(block);
+        }
+
+        let block_size = 1usize << header.block_size_bits;
+        let mut entries = Vec::new();
+        let mut ckey_to_entry_index = HashMap::new();
+        for (i, block) in blocks.iter().enumerate() {
+            let start = block.offset as usize;
+            let block_data = data.get(start..(start + block_size).min(data.len()))
+                .ok_or(Error::TruncatedPatchManifest)?;
+            let checksum: [u8; 16] = Md5::digest(block_data).into();
+            if checksum != block.checksum {
+                return Err(Error::PatchBlockChecksumMismatch(i));
+            }
+
+            // each block's file entries end with a zero patch count
+            let mut block_rest = block_data;
+            while block_rest.first().is_some_and(|&num_patches| num_patches != 0) {
+                let ((new_rest, _), record) = PatchFileRecord::from_bytes((block_rest, 0))?;
+                block_rest = new_rest;
+                ckey_to_entry_index.insert(record.target_ckey.clone(), entries.len());
+                entries.push(PatchFileEntry {
+                    target_ckey: record.target_ckey,
+                    decoded_size: record.decoded_size,
+                    patches: record.patches,
+                });
+            }
+        }
+
+        Ok(PatchManifest {
+            version: header.version,
+            flags: header.flags,
+            encoding_ckey: header.encoding_ckey,
+            encoding_ekey: header.encoding_ekey,
+            encoding_decoded_size: header.encoding_decoded_size,
+            encoding_encoded_size: header.encoding_encoded_size,
+            encoding_espec,
+            entries,
+            ckey_to_entry_index,
+        })
+    }
+
+    pub fn get_entry_for_ckey(&self, ckey: &CKey) -> Option<&PatchFileEntry> {
+        let index = *self.ckey_to_entry_index.get(ckey)?;
+        Some(&self.entries[index])
+    }
+
+    // Finds a patch which turns the file with the given EKey into the target
+    pub fn find_patch(&self, target_ckey: &CKey, source_ekey: &EKey) -> Option<&PatchRecord> {
+        self.get_entry_for_ckey(target_ckey)?
+            .patches.iter()
+            .find(|patch| &patch.source_ekey == source_ekey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (target ckey, size, [(source ekey, patch ekey)])
+    type TestEntry = (CKey, u64, Vec<(EKey, EKey)>);
+
+    // Builds a manifest with a single block out of the given entries
+    fn make_patch_manifest(entries: &[TestEntry]) -> Vec<u8> {
+        let block_size_bits = 10;
+        let mut block = Vec::new();
+        for (target_ckey, size, patches) in entries {
+            block.push(patches.len() as u8);
+            block.extend(target_ckey.0);
+            block.extend(&size.to_be_bytes()[3..]);
+            for (i, (source_ekey, patch_ekey)) in patches.iter().enumerate() {
+                block.extend(source_ekey.0);
+                block.extend(&(size + 1).to_be_bytes()[3..]);
+                block.extend(patch_ekey.0);
+                block.extend(100u32.to_be_bytes());
+                block.push(i as u8);
+            }
+        }
+        block.resize(1 << block_size_bits, 0);
+
+        let espec = b"z";
+        let mut data = b"PA".to_vec();
+        data.extend([2, 16, 16, 16, block_size_bits]);
+        data.extend(1u16.to_be_bytes());
+        data.push(0);
+        data.extend([0xaa; 16]);
+        data.extend([0xbb; 16]);
+        data.extend(1000u32.to_be_bytes());
+        data.extend(500u32.to_be_bytes());
+        data.push(espec.len() as u8);
+        data.extend(espec);
+        let block_offset = data.len() + 16 + 16 + 4;
+        data.extend(entries.last().unwrap().0.0);
+        data.extend(Md5::digest(&block));
+        data.extend((block_offset as u32).to_be_bytes());
+        data.extend(block);
+        data
+    }
+
+    #[test]
+    fn test_patch_manifest() {
+        let data = make_patch_manifest(&[
+            (CKey([1; 16]), 0x1_0000_0000, vec![(EKey([2; 16]), EKey([3; 16])), (EKey([4; 16]), EKey([5; 16]))]),
+            (CKey([6; 16]), 10, vec![(EKey([7; 16]), EKey([8; 16]))]),
+        ]);
+        let manifest = PatchManifest::parse(&data).unwrap();
+        assert_eq!(manifest.encoding_ckey, CKey([0xaa; 16]));
+        assert_eq!(manifest.encoding_espec, "z".parse().unwrap());
+        assert_eq!(manifest.entries.len(), 2);
+
+        let entry = manifest.get_entry_for_ckey(&CKey([1; 16])).unwrap();
+        assert_eq!(entry.decoded_size, 0x1_0000_0000);
+        assert_eq!(entry.patches[1].source_decoded_size, 0x1_0000_0001);
+        assert_eq!(manifest.find_patch(&CKey([1; 16]), &EKey([4; 16])).unwrap().patch_ekey, EKey([5; 16]));
+        assert!(manifest.find_patch(&CKey([6; 16]), &EKey([2; 16])).is_none());
+
+        let mut corrupted = data.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert!(matches!(PatchManifest::parse(&corrupted), Err(Error::PatchBlockChecksumMismatch(0))));
+    }
+}
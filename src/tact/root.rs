@@ -1,37 +1,284 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 
 use deku::{DekuRead, DekuContainerRead};
 
 use crate::error::Error;
-use crate::tact::common::CKey;
+use crate::hashpath::hash_path;
+use crate::listfile::{Listfile, NameHashReport};
+use crate::tact::common::{CKey, EKey};
 use crate::tact::blte::decode_blte;
+use crate::tact::encoding::EncodingFile;
 
-#[derive(DekuRead, Clone, Debug)]
+// Content flags, from https://wowdev.wiki/TACT#Root
+pub const CONTENT_FLAG_INSTALL: u32 = 0x4;
+pub const CONTENT_FLAG_LOAD_ON_WINDOWS: u32 = 0x8;
+pub const CONTENT_FLAG_LOAD_ON_MACOS: u32 = 0x10;
+pub const CONTENT_FLAG_X86_32: u32 = 0x20;
+pub const CONTENT_FLAG_X86_64: u32 = 0x40;
+pub const CONTENT_FLAG_LOW_VIOLENCE: u32 = 0x80;
+pub const CONTENT_FLAG_DO_NOT_LOAD: u32 = 0x100;
+pub const CONTENT_FLAG_UPDATE_PLUGIN: u32 = 0x800;
+pub const CONTENT_FLAG_ARM64: u32 = 0x8000;
+pub const CONTENT_FLAG_ENCRYPTED: u32 = 0x8000000;
+pub const CONTENT_FLAG_NO_NAME_HASH: u32 = 0x10000000;
+pub const CONTENT_FLAG_UNCOMMON_RESOLUTION: u32 = 0x20000000;
+pub const CONTENT_FLAG_BUNDLE: u32 = 0x40000000;
+pub const CONTENT_FLAG_NO_COMPRESSION: u32 = 0x80000000;
+
+// Locale flags
+pub const LOCALE_EN_US: u32 = 0x2;
+pub const LOCALE_KO_KR: u32 = 0x4;
+pub const LOCALE_FR_FR: u32 = 0x10;
+pub const LOCALE_DE_DE: u32 = 0x20;
+pub const LOCALE_ZH_CN: u32 = 0x40;
+pub const LOCALE_ES_ES: u32 = 0x80;
+pub const LOCALE_ZH_TW: u32 = 0x100;
+pub const LOCALE_EN_GB: u32 = 0x200;
+pub const LOCALE_EN_CN: u32 = 0x400;
+pub const LOCALE_EN_TW: u32 = 0x800;
+pub const LOCALE_ES_MX: u32 = 0x1000;
+pub const LOCALE_RU_RU: u32 = 0x2000;
+pub const LOCALE_PT_BR: u32 = 0x4000;
+pub const LOCALE_IT_IT: u32 = 0x8000;
+pub const LOCALE_PT_PT: u32 = 0x10000;
+
+#[derive(Clone, Debug)]
 pub struct RootFileEntry {
     pub ckey: CKey,
+    pub name_hash: Option<u64>,
+    pub content_flags: u32,
+    pub locale_flags: u32,
+}
+
+#[derive(DekuRead)]
+struct LegacyRootRecord {
+    ckey: CKey,
     #[deku(endian = "little")]
-    pub name_hash: u64,
+    name_hash: u64,
 }
 
+// Pre-8.2 root files: a sequence of blocks with interleaved ckeys and name hashes
 #[derive(DekuRead)]
-struct RootBlock {
+struct LegacyRootBlock {
     #[deku(endian = "little")]
     _num_files: u32,
     #[deku(endian = "little")]
-    _content_flags: u32,
+    content_flags: u32,
     #[deku(endian = "little")]
-    _locale_flags: u32,
+    locale_flags: u32,
+    #[deku(count = "_num_files", endian = "little")]
+    file_id_delta_table: Vec<u32>,
     #[deku(count = "_num_files")]
+    records: Vec<LegacyRootRecord>,
+}
+
+#[derive(DekuRead)]
+#[deku(magic = b"TSFM", endian = "little")]
+struct MfstHeader {
+    // Before 10.1.7 the header had no size or version, so these are really
+    // the total and named file counts
+    header_size: u32,
+    version: u32,
+}
+
+impl MfstHeader {
+    // The header size and block version. Newer headers are always 24 bytes,
+    // which older headers would need exactly 24 files to look like.
+    fn get_layout(&self) -> (usize, u32) {
+        if self.header_size == MFST_HEADER_SIZE {
+            (MFST_HEADER_SIZE as usize, self.version)
+        } else {
+            (MFST_LEGACY_HEADER_SIZE, 1)
+        }
+    }
+}
+
+#[derive(DekuRead)]
+struct MfstBlockV1 {
+    #[deku(endian = "little")]
+    _num_files: u32,
+    #[deku(endian = "little")]
+    content_flags: u32,
+    #[deku(endian = "little")]
+    locale_flags: u32,
+    #[deku(count = "_num_files", endian = "little")]
+    file_id_delta_table: Vec<u32>,
+    #[deku(count = "_num_files")]
+    ckeys: Vec<CKey>,
+    #[deku(count = "if content_flags & CONTENT_FLAG_NO_NAME_HASH != 0 { 0 } else { *_num_files }", endian = "little")]
+    name_hashes: Vec<u64>,
+}
+
+// Version 2 splits the content flags across three fields
+#[derive(DekuRead)]
+struct MfstBlockV2 {
+    #[deku(endian = "little")]
+    _num_files: u32,
+    #[deku(endian = "little")]
+    locale_flags: u32,
+    #[deku(endian = "little")]
+    content_flags_1: u32,
+    #[deku(endian = "little")]
+    content_flags_2: u32,
+    content_flags_3: u8,
+    #[deku(count = "_num_files", endian = "little")]
     file_id_delta_table: Vec<u32>,
     #[deku(count = "_num_files")]
-    file_entries: Vec<RootFileEntry>,
+    ckeys: Vec<CKey>,
+    #[deku(count = "if (content_flags_1 | content_flags_2 | ((*content_flags_3 as u32) << 17)) & CONTENT_FLAG_NO_NAME_HASH != 0 { 0 } else { *_num_files }", endian = "little")]
+    name_hashes: Vec<u64>,
+}
+
+// A root block, normalized across all of the root file versions
+struct RootBlock {
+    content_flags: u32,
+    locale_flags: u32,
+    file_id_delta_table: Vec<u32>,
+    ckeys: Vec<CKey>,
+    name_hashes: Option<Vec<u64>>,
+}
+
+impl From<LegacyRootBlock> for RootBlock {
+    fn from(block: LegacyRootBlock) -> Self {
+        let (ckeys, name_hashes) = block.records.into_iter()
+            .map(|record| (record.ckey, record.name_hash))
+            .unzip();
+        RootBlock {
+            content_flags: block.content_flags,
+            locale_flags: block.locale_flags,
+            file_id_delta_table: block.file_id_delta_table,
+            ckeys,
+            name_hashes: Some(name_hashes),
+        }
+    }
+}
+
+impl From<MfstBlockV1> for RootBlock {
+    fn from(block: MfstBlockV1) -> Self {
+        let has_name_hashes = block.content_flags & CONTENT_FLAG_NO_NAME_HASH == 0;
+        RootBlock {
+            content_flags: block.content_flags,
+            locale_flags: block.locale_flags,
+            file_id_delta_table: block.file_id_delta_table,
+            ckeys: block.ckeys,
+            name_hashes: has_name_hashes.then_some(block.name_hashes),
+        }
+    }
+}
+
+impl From<MfstBlockV2> for RootBlock {
+    fn from(block: MfstBlockV2) -> Self {
+        let content_flags = block.content_flags_1 | block.content_flags_2 | ((block.content_flags_3 as u32) << 17);
+        let has_name_hashes = content_flags & CONTENT_FLAG_NO_NAME_HASH == 0;
+        RootBlock {
+            content_flags,
+            locale_flags: block.locale_flags,
+            file_id_delta_table: block.file_id_delta_table,
+            ckeys: block.ckeys,
+            name_hashes: has_name_hashes.then_some(block.name_hashes),
+        }
+    }
 }
 
+// Reads blocks until the data runs out, failing on any that don't parse
+fn read_blocks<T: for<'a> DekuContainerRead<'a> + Into<RootBlock>>(mut rest: &[u8]) -> Result<Vec<RootBlock>, Error> {
+    let mut blocks = Vec::new();
+    while !rest.is_empty() {
+        let ((new_rest, _), block) = T::from_bytes((rest, 0))
+            .map_err(|_| Error::InvalidRootBlock(blocks.len()))?;
+        rest = new_rest;
+        blocks.push(block.into());
+    }
+    Ok(blocks)
+}
+
+// MFST headers from before 10.1.7 are just the magic and two file counts
+const MFST_LEGACY_HEADER_SIZE: usize = 12;
+const MFST_HEADER_SIZE: u32 = 24;
+
+fn read_root_blocks(data: &[u8]) -> Result<Vec<RootBlock>, Error> {
+    let Ok((_, header)) = MfstHeader::from_bytes((data, 0)) else {
+        return read_blocks::<LegacyRootBlock>(data);
+    };
+    let (header_size, version) = header.get_layout();
+    let rest = data.get(header_size..).ok_or(Error::InvalidRootBlock(0))?;
+    match version {
+        1 => read_blocks::<MfstBlockV1>(rest),
+        2 => read_blocks::<MfstBlockV2>(rest),
+        _ => Err(Error::UnsupportedRootVersion(version)),
+    }
+}
+
+pub const LOCALE_NAMES: &[(&str, u32)] = &[
+    ("enUS", LOCALE_EN_US), ("koKR", LOCALE_KO_KR), ("frFR", LOCALE_FR_FR),
+    ("deDE", LOCALE_DE_DE), ("zhCN", LOCALE_ZH_CN), ("esES", LOCALE_ES_ES),
+    ("zhTW", LOCALE_ZH_TW), ("enGB", LOCALE_EN_GB), ("enCN", LOCALE_EN_CN),
+    ("enTW", LOCALE_EN_TW), ("esMX", LOCALE_ES_MX), ("ruRU", LOCALE_RU_RU),
+    ("ptBR", LOCALE_PT_BR), ("itIT", LOCALE_IT_IT), ("ptPT", LOCALE_PT_PT),
+];
+
+// Parses a locale name like "enUS" into its locale flag
+pub fn parse_locale(name: &str) -> Result<u32, Error> {
+    LOCALE_NAMES.iter()
+        .find(|(locale_name, _)| locale_name.eq_ignore_ascii_case(name))
+        .map(|&(_, flag)| flag)
+        .ok_or_else(|| Error::UnknownLocale(name.to_string()))
+}
+
+pub const CONTENT_FLAG_NAMES: &[(&str, u32)] = &[
+    ("install", CONTENT_FLAG_INSTALL), ("windows", CONTENT_FLAG_LOAD_ON_WINDOWS),
+    ("macos", CONTENT_FLAG_LOAD_ON_MACOS), ("x86-32", CONTENT_FLAG_X86_32),
+    ("x86-64", CONTENT_FLAG_X86_64), ("low-violence", CONTENT_FLAG_LOW_VIOLENCE),
+    ("do-not-load", CONTENT_FLAG_DO_NOT_LOAD), ("update-plugin", CONTENT_FLAG_UPDATE_PLUGIN),
+    ("arm64", CONTENT_FLAG_ARM64), ("encrypted", CONTENT_FLAG_ENCRYPTED),
+    ("no-name-hash", CONTENT_FLAG_NO_NAME_HASH), ("uncommon-resolution", CONTENT_FLAG_UNCOMMON_RESOLUTION),
+    ("bundle", CONTENT_FLAG_BUNDLE), ("no-compression", CONTENT_FLAG_NO_COMPRESSION),
+];
+
+// Parses a content flag name like "low-violence" into its flag
+pub fn parse_content_flag(name: &str) -> Result<u32, Error> {
+    CONTENT_FLAG_NAMES.iter()
+        .find(|(flag_name, _)| flag_name.eq_ignore_ascii_case(name))
+        .map(|&(_, flag)| flag)
+        .ok_or_else(|| Error::UnknownContentFlag(name.to_string()))
+}
+
+// Restricts which root entries are considered, e.g. to build a Windows-only
+// sheepfile without LowViolence variants
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RootEntryFilter {
+    // Entries must have all of these content flags set...
+    pub required_content_flags: u32,
+    // ...and none of these
+    pub excluded_content_flags: u32,
+    // If set, entries must also have one of these CKeys, e.g. the files in a
+    // download manifest's priority 0 set
+    pub ckeys: Option<HashSet<CKey>>,
+}
+
+impl RootEntryFilter {
+    pub fn matches(&self, entry: &RootFileEntry) -> bool {
+        entry.content_flags & self.required_content_flags == self.required_content_flags
+            && entry.content_flags & self.excluded_content_flags == 0
+            && self.ckeys.as_ref().is_none_or(|ckeys| ckeys.contains(&entry.ckey))
+    }
+}
+
+pub const DEFAULT_LOCALE_PREFERENCE: &[u32] = &[LOCALE_EN_US, LOCALE_EN_GB];
+
 #[derive(Clone)]
 pub struct RootFile {
     pub entries: Vec<RootFileEntry>,
+    // Every (locale, content flags, ckey) variant of a file, in file order
+    pub file_id_to_entry_indices: HashMap<u32, Vec<usize>>,
+    pub name_hash_to_entry_indices: HashMap<u64, Vec<usize>>,
+    // The variant picked for each file according to the locale preference
     pub file_id_to_entry_index: HashMap<u32, usize>,
     pub name_hash_to_entry_index: HashMap<u64, usize>,
+    locale_preference: Vec<u32>,
+    entry_filter: RootEntryFilter,
+    ckey_to_file_ids: OnceLock<HashMap<CKey, Vec<u32>>>,
 }
 
 impl RootFile {
@@ -39,30 +286,102 @@ impl RootFile {
         let decode = decode_blte(data)?;
 
         let mut entries = Vec::new();
-        let mut file_id_to_entry_index = HashMap::new();
-        let mut name_hash_to_entry_index = HashMap::new();
-        let mut rest = &decode[..];
-        loop {
-            let Ok(((new_rest, _), block)) = RootBlock::from_bytes((rest, 0)) else {
-                break;
-            };
-            rest = new_rest;
+        let mut file_id_to_entry_indices: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut name_hash_to_entry_indices: HashMap<u64, Vec<usize>> = HashMap::new();
+        for block in read_root_blocks(&decode)? {
+            let name_hashes = block.name_hashes.map(|hashes| hashes.into_iter().map(Some).collect())
+                .unwrap_or_else(|| vec![None; block.ckeys.len()]);
 
             let mut file_id = 0;
-            for (file_id_delta, entry) in std::iter::zip(block.file_id_delta_table, block.file_entries) {
+            for ((file_id_delta, ckey), name_hash) in block.file_id_delta_table.into_iter().zip(block.ckeys).zip(name_hashes) {
                 file_id += file_id_delta;
-                file_id_to_entry_index.insert(file_id, entries.len());
-                name_hash_to_entry_index.insert(entry.name_hash, entries.len());
+                file_id_to_entry_indices.entry(file_id).or_default().push(entries.len());
+                if let Some(name_hash) = name_hash {
+                    name_hash_to_entry_indices.entry(name_hash).or_default().push(entries.len());
+                }
                 file_id += 1;
-                entries.push(entry);
+                entries.push(RootFileEntry {
+                    ckey,
+                    name_hash,
+                    content_flags: block.content_flags,
+                    locale_flags: block.locale_flags,
+                });
             }
         }
 
-        Ok(RootFile {
+        let mut root = RootFile {
             entries,
-            file_id_to_entry_index,
-            name_hash_to_entry_index,
-        })
+            file_id_to_entry_indices,
+            name_hash_to_entry_indices,
+            file_id_to_entry_index: HashMap::new(),
+            name_hash_to_entry_index: HashMap::new(),
+            locale_preference: DEFAULT_LOCALE_PREFERENCE.to_vec(),
+            entry_filter: RootEntryFilter::default(),
+            ckey_to_file_ids: OnceLock::new(),
+        };
+        root.resolve_variants();
+        Ok(root)
+    }
+
+    pub fn get_locale_preference(&self) -> &[u32] {
+        &self.locale_preference
+    }
+
+    // Sets which locales' variants are preferred, most preferred first. Files
+    // with no variant in any of these locales fall back to their first variant.
+    pub fn set_locale_preference(&mut self, locales: &[u32]) {
+        self.locale_preference = locales.to_vec();
+        self.resolve_variants();
+    }
+
+    pub fn get_entry_filter(&self) -> &RootEntryFilter {
+        &self.entry_filter
+    }
+
+    // Files with no variant matching the filter are dropped entirely
+    pub fn set_entry_filter(&mut self, filter: RootEntryFilter) {
+        self.entry_filter = filter;
+        self.resolve_variants();
+    }
+
+    fn resolve_variants(&mut self) {
+        let filter = &self.entry_filter;
+        self.file_id_to_entry_index = self.file_id_to_entry_indices.iter()
+            .filter_map(|(&file_id, indices)| Some((file_id, self.pick_variant(indices, filter)?)))
+            .collect();
+        self.name_hash_to_entry_index = self.name_hash_to_entry_indices.iter()
+            .filter_map(|(&name_hash, indices)| Some((name_hash, self.pick_variant(indices, filter)?)))
+            .collect();
+        self.ckey_to_file_ids = OnceLock::new();
+    }
+
+    fn pick_variant(&self, indices: &[usize], filter: &RootEntryFilter) -> Option<usize> {
+        let candidates: Vec<usize> = indices.iter()
+            .copied()
+            .filter(|&index| filter.matches(&self.entries[index]))
+            .collect();
+        let first = *candidates.first()?;
+        Some(self.locale_preference.iter()
+            .find_map(|&locale| candidates.iter().copied().find(|&index| self.entries[index].locale_flags & locale != 0))
+            .unwrap_or(first))
+    }
+
+    // Picks a file's variant as usual, but only among those which also match
+    // an extra filter
+    pub fn get_entry_for_file_id_with_filter(&self, file_id: u32, filter: &RootEntryFilter) -> Option<&RootFileEntry> {
+        let candidates: Vec<usize> = self.file_id_to_entry_indices.get(&file_id)?
+            .iter()
+            .copied()
+            .filter(|&index| filter.matches(&self.entries[index]))
+            .collect();
+        self.pick_variant(&candidates, &self.entry_filter).map(|index| &self.entries[index])
+    }
+
+    pub fn get_variants_for_file_id(&self, file_id: u32) -> impl Iterator<Item=&RootFileEntry> {
+        self.file_id_to_entry_indices.get(&file_id)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.entries[index])
     }
 
     fn get_entry_ckey(&self, entry_index: usize) -> &CKey {
@@ -73,18 +392,133 @@ impl RootFile {
         self.file_id_to_entry_index.get(&file_id).map(|index| self.get_entry_ckey(*index))
     }
     
-    // from https://wowdev.wiki/TACT#hashpath
+    // The same content can be shared by several file IDs
+    pub fn get_file_ids_for_ckey(&self, ckey: &CKey) -> &[u32] {
+        let ckey_to_file_ids = self.ckey_to_file_ids.get_or_init(|| {
+            let mut ckey_to_file_ids: HashMap<CKey, Vec<u32>> = HashMap::new();
+            for (&file_id, &index) in &self.file_id_to_entry_index {
+                ckey_to_file_ids.entry(self.get_entry_ckey(index).clone()).or_default().push(file_id);
+            }
+            for file_ids in ckey_to_file_ids.values_mut() {
+                file_ids.sort();
+            }
+            ckey_to_file_ids
+        });
+        ckey_to_file_ids.get(ckey).map(|file_ids| file_ids.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn get_file_ids_for_ekey(&self, encoding: &EncodingFile, ekey: &EKey) -> &[u32] {
+        match encoding.get_ckey_for_ekey(ekey) {
+            Some(ckey) => self.get_file_ids_for_ckey(ckey),
+            None => &[],
+        }
+    }
+
     pub fn get_ckey_for_file_path(&self, name: &str) -> Option<&CKey> {
-        let normalized = name.to_ascii_uppercase().replace("/", "\\");
-        let name_hash = hashers::jenkins::lookup3(normalized.as_bytes());
+        let name_hash = hash_path(name);
         self.name_hash_to_entry_index.get(&name_hash).map(|index| self.get_entry_ckey(*index))
     }
-}
 
+    // Resolves a real path through the listfile, falling back to its name
+    // hash for files the listfile doesn't know about
+    pub fn get_ckey_for_listfile_path(&self, listfile: &Listfile, path: &str) -> Option<&CKey> {
+        listfile.get_file_id_for_path(path)
+            .and_then(|file_id| self.get_ckey_for_file_id(file_id))
+            .or_else(|| self.get_ckey_for_file_path(path))
+    }
+
+    // Lists the (file id, path) pairs in this root file matching a glob pattern
+    pub fn glob_listfile<'a>(&'a self, listfile: &'a Listfile, pattern: &str) -> impl Iterator<Item=(u32, &'a str)> + 'a {
+        listfile.glob(pattern)
+            .filter(|(file_id, _)| self.file_id_to_entry_index.contains_key(file_id))
+    }
+
+    pub fn check_listfile_name_hashes(&self, listfile: &Listfile) -> NameHashReport {
+        listfile.check_name_hashes(self.file_id_to_entry_index.iter()
+            .map(|(&file_id, &index)| (file_id, self.entries[index].name_hash)))
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tact::blte::blte_wrap;
+
+    // (file id, ckey, name hash)
+    type TestEntry = (u32, CKey, u64);
+
+    // Builds a root file out of (content flags, locale flags, entries)
+    // blocks. Version 0 is the legacy format, otherwise it's an MFST version.
+    fn make_root_file_version(version: u32, blocks: &[(u32, u32, Vec<TestEntry>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let total_files: usize = blocks.iter().map(|(_, _, entries)| entries.len()).sum();
+        if version > 0 {
+            data.extend(b"TSFM");
+            data.extend(24u32.to_le_bytes());
+            data.extend(version.to_le_bytes());
+            data.extend((total_files as u32).to_le_bytes());
+            data.extend((total_files as u32).to_le_bytes());
+            data.extend(0u32.to_le_bytes());
+        }
+        data.extend(make_root_blocks(version, blocks));
+        blte_wrap(&data)
+    }
+
+    fn make_root_blocks(version: u32, blocks: &[(u32, u32, Vec<TestEntry>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (content_flags, locale_flags, entries) in blocks {
+            data.extend((entries.len() as u32).to_le_bytes());
+            if version == 2 {
+                data.extend(locale_flags.to_le_bytes());
+                data.extend((content_flags & 0x1ffff).to_le_bytes());
+                data.extend((content_flags & !0x1ffffff).to_le_bytes());
+                data.push((content_flags >> 17) as u8);
+            } else {
+                data.extend(content_flags.to_le_bytes());
+                data.extend(locale_flags.to_le_bytes());
+            }
+            let mut next_file_id = 0;
+            for (file_id, _, _) in entries {
+                data.extend((file_id - next_file_id).to_le_bytes());
+                next_file_id = file_id + 1;
+            }
+            if version == 0 {
+                for (_, ckey, name_hash) in entries {
+                    data.extend(ckey.0);
+                    data.extend(name_hash.to_le_bytes());
+                }
+            } else {
+                for (_, ckey, _) in entries {
+                    data.extend(ckey.0);
+                }
+                if content_flags & CONTENT_FLAG_NO_NAME_HASH == 0 {
+                    for (_, _, name_hash) in entries {
+                        data.extend(name_hash.to_le_bytes());
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    fn make_root_file(blocks: &[(u32, u32, Vec<TestEntry>)]) -> Vec<u8> {
+        make_root_file_version(0, blocks)
+    }
+
+    #[test]
+    fn test_root_file_ckey_lookup() {
+        let shared = CKey([1; 16]);
+        let other = CKey([2; 16]);
+        let data = make_root_file(&[
+            (0, 0x2, vec![(10, shared.clone(), 100), (12, other.clone(), 101)]),
+            (0, 0x2, vec![(20, shared.clone(), 102)]),
+        ]);
+        let root = RootFile::parse(&data).unwrap();
+
+        assert_eq!(root.get_ckey_for_file_id(12), Some(&other));
+        assert_eq!(root.get_file_ids_for_ckey(&shared), &[10, 20]);
+        assert!(root.get_file_ids_for_ckey(&CKey([3; 16])).is_empty());
+    }
 
     #[test]
     fn test_root_file() {
@@ -93,4 +527,168 @@ mod tests {
         let file = RootFile::parse(&test_file).unwrap();
         dbg!(file.file_id_to_entry_index.len());
     }
+
+    #[test]
+    fn test_root_file_mfst() {
+        for version in [1, 2] {
+            let data = make_root_file_version(version, &[
+                (CONTENT_FLAG_LOAD_ON_WINDOWS, LOCALE_EN_US, vec![(5, CKey([5; 16]), 500), (7, CKey([7; 16]), 700)]),
+                (CONTENT_FLAG_NO_NAME_HASH | CONTENT_FLAG_LOW_VIOLENCE, LOCALE_DE_DE, vec![(8, CKey([8; 16]), 0)]),
+            ]);
+            let root = RootFile::parse(&data).unwrap();
+
+            assert_eq!(root.get_ckey_for_file_id(7), Some(&CKey([7; 16])));
+            assert_eq!(root.get_ckey_for_file_id(8), Some(&CKey([8; 16])));
+            let entry = &root.entries[root.file_id_to_entry_index[&8]];
+            assert_eq!(entry.name_hash, None);
+            assert_eq!(entry.content_flags, CONTENT_FLAG_NO_NAME_HASH | CONTENT_FLAG_LOW_VIOLENCE);
+            assert_eq!(entry.locale_flags, LOCALE_DE_DE);
+            let entry = &root.entries[root.name_hash_to_entry_index[&500]];
+            assert_eq!(entry.content_flags, CONTENT_FLAG_LOAD_ON_WINDOWS);
+            assert_eq!(entry.ckey, CKey([5; 16]));
+        }
+    }
+
+    #[test]
+    fn test_root_file_mfst_headers() {
+        let blocks = [(0, LOCALE_EN_US, vec![(1, CKey([1; 16]), 100), (3, CKey([3; 16]), 300)])];
+
+        // pre-10.1.7, the header is just the total and named file counts
+        let mut legacy = b"TSFM".to_vec();
+        legacy.extend(2u32.to_le_bytes());
+        legacy.extend(2u32.to_le_bytes());
+        legacy.extend(make_root_blocks(1, &blocks));
+        let root = RootFile::parse(&blte_wrap(&legacy)).unwrap();
+        assert_eq!(root.entries.len(), 2);
+        assert_eq!(root.get_ckey_for_file_id(3), Some(&CKey([3; 16])));
+
+        for version in [1, 2] {
+            let root = RootFile::parse(&make_root_file_version(version, &blocks)).unwrap();
+            assert_eq!(root.entries.len(), 2);
+            assert_eq!(root.get_ckey_for_file_id(3), Some(&CKey([3; 16])));
+        }
+        let data = make_root_file_version(3, &blocks);
+        assert!(matches!(RootFile::parse(&data), Err(Error::UnsupportedRootVersion(3))));
+    }
+
+    #[test]
+    fn test_root_file_bad_blocks() {
+        let blocks = [
+            (0, LOCALE_EN_US, vec![(1, CKey([1; 16]), 100)]),
+            (0, LOCALE_EN_US, vec![(2, CKey([2; 16]), 200)]),
+        ];
+        for version in [0, 1, 2] {
+            let data = make_root_file_version(version, &blocks);
+            let truncated = &data[..data.len() - 1];
+            assert!(matches!(RootFile::parse(truncated), Err(Error::InvalidRootBlock(1))));
+
+            let mut trailing = data.clone();
+            trailing.extend([0xff; 3]);
+            assert!(matches!(RootFile::parse(&trailing), Err(Error::InvalidRootBlock(2))));
+        }
+    }
+
+    #[test]
+    fn test_root_file_locales() {
+        let data = make_root_file_version(1, &[
+            (0, LOCALE_DE_DE, vec![(1, CKey([1; 16]), 100), (2, CKey([2; 16]), 200)]),
+            (0, LOCALE_EN_GB, vec![(1, CKey([3; 16]), 100)]),
+            (0, LOCALE_EN_US | LOCALE_EN_GB, vec![(2, CKey([4; 16]), 200)]),
+        ]);
+        let mut root = RootFile::parse(&data).unwrap();
+        assert_eq!(root.get_variants_for_file_id(1).count(), 2);
+        assert_eq!(root.get_ckey_for_file_id(1), Some(&CKey([3; 16])));
+        assert_eq!(root.get_ckey_for_file_id(2), Some(&CKey([4; 16])));
+        assert_eq!(root.entries[root.name_hash_to_entry_index[&200]].ckey, CKey([4; 16]));
+
+        root.set_locale_preference(&[parse_locale("deDE").unwrap()]);
+        assert_eq!(root.get_ckey_for_file_id(1), Some(&CKey([1; 16])));
+        assert_eq!(root.get_file_ids_for_ckey(&CKey([2; 16])), &[2]);
+
+        // nothing matches, so fall back to the first variant
+        root.set_locale_preference(&[LOCALE_KO_KR]);
+        assert_eq!(root.get_ckey_for_file_id(1), Some(&CKey([1; 16])));
+        assert!(parse_locale("xxXX").is_err());
+        assert_eq!(parse_content_flag("Low-Violence").unwrap(), CONTENT_FLAG_LOW_VIOLENCE);
+        assert!(matches!(parse_content_flag("lowviolence"), Err(Error::UnknownContentFlag(_))));
+    }
+
+    #[test]
+    fn test_root_file_entry_filter() {
+        let data = make_root_file_version(2, &[
+            (CONTENT_FLAG_LOAD_ON_WINDOWS, LOCALE_EN_US, vec![(1, CKey([1; 16]), 100)]),
+            (CONTENT_FLAG_LOAD_ON_MACOS, LOCALE_EN_US, vec![(1, CKey([2; 16]), 100)]),
+            (CONTENT_FLAG_LOAD_ON_WINDOWS | CONTENT_FLAG_LOW_VIOLENCE, LOCALE_EN_US, vec![(2, CKey([3; 16]), 200)]),
+        ]);
+        let mut root = RootFile::parse(&data).unwrap();
+        let windows = RootEntryFilter { required_content_flags: CONTENT_FLAG_LOAD_ON_WINDOWS, ..RootEntryFilter::default() };
+        let mac = RootEntryFilter { required_content_flags: CONTENT_FLAG_LOAD_ON_MACOS, ..RootEntryFilter::default() };
+        assert_eq!(root.get_entry_for_file_id_with_filter(1, &mac).unwrap().ckey, CKey([2; 16]));
+
+        root.set_entry_filter(RootEntryFilter {
+            excluded_content_flags: CONTENT_FLAG_LOW_VIOLENCE,
+            ..windows.clone()
+        });
+        assert_eq!(root.get_ckey_for_file_id(1), Some(&CKey([1; 16])));
+        assert_eq!(root.get_ckey_for_file_id(2), None);
+        assert!(root.get_entry_for_file_id_with_filter(1, &mac).is_none());
+
+        root.set_entry_filter(RootEntryFilter::default());
+        assert_eq!(root.get_ckey_for_file_id(2), Some(&CKey([3; 16])));
+
+        let download_set = RootEntryFilter {
+            ckeys: Some([CKey([2; 16]), CKey([3; 16])].into_iter().collect()),
+            ..RootEntryFilter::default()
+        };
+        assert_eq!(root.get_entry_for_file_id_with_filter(1, &download_set).unwrap().ckey, CKey([2; 16]));
+        assert_eq!(root.get_entry_for_file_id_with_filter(2, &download_set).unwrap().ckey, CKey([3; 16]));
+        assert!(root.get_entry_for_file_id_with_filter(1, &RootEntryFilter { ckeys: Some(HashSet::new()), ..windows }).is_none());
+    }
+
+    #[test]
+    fn test_root_file_path_lookup() {
+        // 24 bytes long, which lookup3 implementations often get wrong
+        let path = "world/maps/azeroth/a.wdt";
+        // name hashes of real files, as root files store them, rather than
+        // ones from hash_path (these were computed with the hashers crate's
+        // independent lookup3 implementation)
+        let data = make_root_file_version(1, &[
+            (0, LOCALE_EN_US, vec![
+                (1, CKey([1; 16]), hash_path(path)),
+                (2, CKey([2; 16]), hash_path("b.blp")),
+                (3, CKey([3; 16]), 0x9eb59e3c76124837),
+                (4, CKey([4; 16]), 0x19a3c4cbc40e9fa2),
+            ]),
+        ]);
+        let root = RootFile::parse(&data).unwrap();
+        assert_eq!(root.get_ckey_for_file_path("Interface/Icons/INV_Misc_QuestionMark.blp"), Some(&CKey([3; 16])));
+        assert_eq!(root.get_ckey_for_file_path("World\\Maps\\Azeroth\\Azeroth.wdt"), Some(&CKey([4; 16])));
+        assert_eq!(root.get_ckey_for_file_path("WORLD\\MAPS\\AZEROTH\\A.WDT"), Some(&CKey([1; 16])));
+        assert_eq!(root.get_ckey_for_file_path("b.blp"), Some(&CKey([2; 16])));
+        assert_eq!(root.get_ckey_for_file_path("c.blp"), None);
+    }
+
+    #[test]
+    fn test_root_file_listfile() {
+        let data = make_root_file_version(1, &[
+            (0, LOCALE_EN_US, vec![(1, CKey([1; 16]), hash_path("a/one.blp")), (2, CKey([2; 16]), 0)]),
+            (CONTENT_FLAG_NO_NAME_HASH, LOCALE_EN_US, vec![(3, CKey([3; 16]), 0)]),
+        ]);
+        let root = RootFile::parse(&data).unwrap();
+        let listfile = Listfile::parse("1;a/one.blp
+2;a/two.blp
+3;b/three.blp
+9;a/nine.blp").unwrap();
+
+        assert_eq!(root.get_ckey_for_listfile_path(&listfile, "b/three.blp"), Some(&CKey([3; 16])));
+        assert_eq!(root.get_ckey_for_listfile_path(&listfile, "A/ONE.BLP"), Some(&CKey([1; 16])));
+        let file_ids: Vec<u32> = root.glob_listfile(&listfile, "a/*").map(|(file_id, _)| file_id).collect();
+        assert_eq!(file_ids, vec![1, 2]);
+
+        let report = root.check_listfile_name_hashes(&listfile);
+        assert_eq!(report.matching, vec![1]);
+        assert_eq!(report.mismatching.len(), 1);
+        assert_eq!(report.mismatching[0].file_id, 2);
+        assert_eq!(report.unhashed, vec![3]);
+    }
 }
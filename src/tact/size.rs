@@ -0,0 +1,122 @@
+use deku::{DekuRead, DekuContainerRead};
+
+use crate::error::Error;
+use crate::tact::blte::decode_blte;
+use crate::tact::common::{get_tag_sizes, get_tagged_entry_indices, RawTag, Tag};
+
+// The size manifest, giving the encoded size of every file so clients can
+// report install footprints. Its keys are truncated EKeys. See
+// https://wowdev.wiki/TACT#Size_manifest
+#[derive(Clone, Debug)]
+pub struct SizeManifest {
+    pub version: u8,
+    pub total_size: u64,
+    pub tags: Vec<Tag>,
+    pub entries: Vec<SizeEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SizeEntry {
+    // the first bytes of the file's EKey
+    pub ekey_prefix: Vec<u8>,
+    pub key_hash: u16,
+    pub size: u64,
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(ctx = "ekey_size: usize, size_bytes: usize")]
+struct RawSizeEntry {
+    #[deku(count = "ekey_size")]
+    ekey_prefix: Vec<u8>,
+    #[deku(endian = "big")]
+    key_hash: u16,
+    #[deku(count = "size_bytes")]
+    size: Vec<u8>,
+}
+
+#[derive(DekuRead, Debug)]
+#[deku(magic = b"DS")]
+struct SizeManifestData {
+    #[deku(assert = "(1..=2).contains(version)")]
+    version: u8,
+    #[deku(assert = "*_ekey_size <= 16")]
+    _ekey_size: u8,
+    #[deku(endian = "big")]
+    _num_entries: u32,
+    #[deku(endian = "big")]
+    _num_tags: u16,
+    #[deku(cond = "*version == 1", default = "0", endian = "big")]
+    total_size_v1: u64,
+    #[deku(cond = "*version == 1", default = "4", assert = "*_size_bytes <= 8")]
+    _size_bytes: u8,
+    #[deku(cond = "*version >= 2", default = "0", endian = "big", bytes = 5)]
+    total_size_v2: u64,
+    #[deku(count = "_num_tags", ctx = "*_num_entries as usize")]
+    tags: Vec<RawTag>,
+    #[deku(count = "_num_entries", ctx = "*_ekey_size as usize, *_size_bytes as usize")]
+    entries: Vec<RawSizeEntry>,
+}
+
+impl SizeManifest {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let decode = decode_blte(data)?;
+        let (_, manifest) = SizeManifestData::from_bytes((&decode, 0))?;
+        Ok(SizeManifest {
+            version: manifest.version,
+            total_size: manifest.total_size_v1.max(manifest.total_size_v2),
+            tags: manifest.tags.into_iter().map(Tag::from).collect(),
+            entries: manifest.entries.into_iter()
+                .map(|entry| SizeEntry {
+                    ekey_prefix: entry.ekey_prefix,
+                    key_hash: entry.key_hash,
+                    size: entry.size.iter().fold(0, |size, &b| (size << 8) | b as u64),
+                })
+                .collect(),
+        })
+    }
+
+    // Finds the entries which have every one of the named tags
+    pub fn get_entries_with_tags(&self, tag_names: &[&str]) -> Result<Vec<&SizeEntry>, Error> {
+        let indices = get_tagged_entry_indices(&self.tags, tag_names, self.entries.len())?;
+        Ok(indices.into_iter().map(|i| &self.entries[i]).collect())
+    }
+
+    // The total size of each tag's files
+    pub fn get_tag_sizes(&self) -> Vec<(String, u64)> {
+        get_tag_sizes(&self.tags, self.entries.len(), |i| self.entries[i].size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tact::blte::blte_wrap;
+
+    #[test]
+    fn test_size_manifest() {
+        let mut data = b"DS".to_vec();
+        data.extend([2, 9]);
+        data.extend(2u32.to_be_bytes());
+        data.extend(2u16.to_be_bytes());
+        data.extend(&0x1_0000_0010_u64.to_be_bytes()[3..]);
+        data.extend(b"Windows\0");
+        data.extend(1u16.to_be_bytes());
+        data.push(0b1100_0000);
+        data.extend(b"deDE\0");
+        data.extend(3u16.to_be_bytes());
+        data.push(0b0100_0000);
+        for (i, size) in [0x1000_0000_u32, 0x10].iter().enumerate() {
+            data.extend([i as u8 + 1; 9]);
+            data.extend(0x1234u16.to_be_bytes());
+            data.extend(size.to_be_bytes());
+        }
+        let data = blte_wrap(&data);
+
+        let manifest = SizeManifest::parse(&data).unwrap();
+        assert_eq!(manifest.total_size, 0x1_0000_0010);
+        assert_eq!(manifest.entries[1].ekey_prefix, vec![2; 9]);
+        assert_eq!(manifest.entries[1].size, 0x10);
+        assert_eq!(manifest.get_entries_with_tags(&["Windows", "deDE"]).unwrap().len(), 1);
+        assert_eq!(manifest.get_tag_sizes(), vec![("Windows".into(), 0x1000_0010), ("deDE".into(), 0x10)]);
+    }
+}